@@ -0,0 +1,47 @@
+use multisnake_shared::{AccountRequest, LoginResponse};
+
+/// Logs in to `name` on the server, registering the account first if asked.
+/// Returns the server's reason when it refuses.
+pub fn login(
+    server_addr: &str,
+    name: &str,
+    password: &str,
+    register: bool,
+) -> Result<LoginResponse, String> {
+    let request = AccountRequest {
+        name: name.to_string(),
+        password: password.to_string(),
+    };
+    if register {
+        post(server_addr, "/accounts/register", &request)?;
+    }
+    let mut response = post(server_addr, "/accounts/login", &request)?;
+    response
+        .body_mut()
+        .read_json()
+        .map_err(|err| format!("Unexpected answer from the server: {}", err))
+}
+
+fn post(
+    server_addr: &str,
+    path: &str,
+    request: &AccountRequest,
+) -> Result<ureq::http::Response<ureq::Body>, String> {
+    let url = format!("http://{}{}", server_addr, path);
+    let mut response = ureq::post(&url)
+        .config()
+        .http_status_as_error(false)
+        .build()
+        .send_json(request)
+        .map_err(|err| format!("Could not reach the server: {}", err))?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let reason = response
+        .body_mut()
+        .read_to_string()
+        .ok()
+        .filter(|reason| !reason.is_empty())
+        .unwrap_or_else(|| response.status().to_string());
+    Err(reason)
+}
@@ -1,7 +1,8 @@
 use macroquad::prelude::*;
 use std::collections::VecDeque;
+use uuid::Uuid;
 
-use multisnake_shared::{GRID_H, GRID_W, Pos};
+use multisnake_shared::{GRID_H, GRID_W, Pos, ScoreEntry};
 
 pub const CELL_SIZE: f32 = 15.0;
 pub const WINDOW_W: f32 = GRID_W as f32 * CELL_SIZE;
@@ -15,6 +16,8 @@ const ME_BODY_COLOR: Color = Color::from_rgba(37, 143, 139, 255);
 const OTHER_HEAD_COLOR: Color = Color::from_rgba(219, 37, 55, 255);
 const OTHER_BODY_COLOR: Color = Color::from_rgba(173, 28, 42, 255);
 const FOOD_COLOR: Color = Color::from_rgba(104, 207, 91, 255);
+const HUD_COLOR: Color = Color::from_rgba(200, 200, 200, 255);
+const HUD_FONT_SIZE: f32 = 20.0;
 
 fn lerp(a: f32, b: f32, t: f32) -> f32 {
     a + (b - a) * t
@@ -94,6 +97,76 @@ pub fn draw_food(food: Pos) {
     let y = food.y as f32 * CELL_SIZE;
     draw_rectangle(x, y, CELL_SIZE, CELL_SIZE, FOOD_COLOR);
 }
+pub fn draw_ping(rtt_ms: Option<u32>) {
+    let text = match rtt_ms {
+        Some(rtt_ms) => format!("ping: {} ms", rtt_ms),
+        None => "ping: -".to_string(),
+    };
+    draw_text(&text, 8.0, HUD_FONT_SIZE, HUD_FONT_SIZE, HUD_COLOR);
+}
+
+/// Replay position in the top left corner, the keys at the bottom.
+pub fn draw_replay_status(status: &str) {
+    draw_text(status, 8.0, HUD_FONT_SIZE, HUD_FONT_SIZE, HUD_COLOR);
+    draw_text(
+        "space pause   left/right seek   up/down speed   tab follow   esc quit",
+        8.0,
+        WINDOW_H - HUD_FONT_SIZE / 2.0,
+        HUD_FONT_SIZE,
+        HUD_COLOR,
+    );
+}
+
+pub fn draw_scoreboard(entries: &[ScoreEntry], my_id: Uuid) {
+    for (i, entry) in entries.iter().enumerate() {
+        let rtt = match entry.rtt_ms {
+            _ if entry.bot => "bot".to_string(),
+            Some(rtt_ms) => format!("{} ms", rtt_ms),
+            None => "-".to_string(),
+        };
+        let text = match entry.rating {
+            Some(rating) => format!("{:>4}  {:>3}  {:>7}", rating, entry.length, rtt),
+            None => format!("{:>3}  {:>7}", entry.length, rtt),
+        };
+        let color = if entry.id == my_id {
+            ME_HEAD_COLOR
+        } else {
+            HUD_COLOR
+        };
+        let dims = measure_text(&text, None, HUD_FONT_SIZE as u16, 1.0);
+        draw_text(
+            &text,
+            WINDOW_W - dims.width - 8.0,
+            HUD_FONT_SIZE * (i + 1) as f32,
+            HUD_FONT_SIZE,
+            color,
+        );
+    }
+}
+
+pub fn draw_shutdown_notice(reason: &str, seconds_left: u64) {
+    let text = format!("{} Closing in {} s.", reason, seconds_left);
+    let dims = measure_text(&text, None, HUD_FONT_SIZE as u16, 1.0);
+    draw_text(
+        &text,
+        (WINDOW_W - dims.width) / 2.0,
+        WINDOW_H - HUD_FONT_SIZE,
+        HUD_FONT_SIZE,
+        GHOST_HEAD_COLOR,
+    );
+}
+
+pub fn draw_server_notice(message: &str) {
+    let dims = measure_text(message, None, HUD_FONT_SIZE as u16, 1.0);
+    draw_text(
+        message,
+        (WINDOW_W - dims.width) / 2.0,
+        WINDOW_H - 2.5 * HUD_FONT_SIZE,
+        HUD_FONT_SIZE,
+        WHITE,
+    );
+}
+
 pub fn draw_game_finished() {
     clear_background(BLACK);
     let text = "Game finished!";
@@ -0,0 +1,54 @@
+use macroquad::input::utils;
+use macroquad::miniquad::{self, KeyMods};
+use macroquad::prelude::KeyCode;
+
+use multisnake_shared::Direction;
+
+const KEY_BINDINGS: [(KeyCode, KeyCode, Direction); 4] = [
+    (KeyCode::Up, KeyCode::W, Direction::Up),
+    (KeyCode::Down, KeyCode::S, Direction::Down),
+    (KeyCode::Left, KeyCode::A, Direction::Left),
+    (KeyCode::Right, KeyCode::D, Direction::Right),
+];
+
+/// Turns typed on the keyboard, kept in the order they were pressed so that
+/// quick double turns within a frame are not reordered.
+pub struct Keyboard {
+    subscriber: usize,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            subscriber: utils::register_input_subscriber(),
+        }
+    }
+
+    /// Directions pressed since the last call, oldest first. Presses pile up
+    /// until the next call, so it has to run every frame.
+    pub fn pressed_directions(&mut self) -> Vec<Direction> {
+        let mut presses = Presses(Vec::new());
+        utils::repeat_all_miniquad_input(&mut presses, self.subscriber);
+        presses.0
+    }
+}
+
+struct Presses(Vec<Direction>);
+
+impl miniquad::EventHandler for Presses {
+    fn update(&mut self) {}
+
+    fn draw(&mut self) {}
+
+    fn key_down_event(&mut self, keycode: KeyCode, _keymods: KeyMods, repeat: bool) {
+        if repeat {
+            return;
+        }
+        if let Some((_, _, dir)) = KEY_BINDINGS
+            .iter()
+            .find(|(key, alt_key, _)| keycode == *key || keycode == *alt_key)
+        {
+            self.0.push(*dir);
+        }
+    }
+}
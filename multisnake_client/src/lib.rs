@@ -0,0 +1,8 @@
+//! Connection and game state of the client, free of any window or terminal
+//! so that headless clients can reuse them.
+
+pub mod account;
+pub mod offline;
+pub mod replay;
+pub mod room_connection;
+pub mod room_state;
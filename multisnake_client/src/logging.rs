@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+
+/// Sends logs to the file at `path`, the terminal belongs to the TUI.
+/// `level` is a filter such as `info` or `multisnake_client=debug`;
+/// `RUST_LOG` takes precedence over it. Logging stays off if the file cannot be created.
+pub fn init(path: &str, level: &str) {
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Could not create log file {:?}: {}", path, err);
+            return;
+        }
+    };
+
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .init();
+}
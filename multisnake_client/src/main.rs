@@ -1,36 +1,124 @@
 mod draw;
-mod room_connection;
-mod room_state;
+mod input;
+mod logging;
+mod playback;
 mod tui;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use macroquad::prelude::*;
+use multisnake_client::account;
+use multisnake_client::offline::{self, OfflineConfig};
+use multisnake_client::room_connection;
+use multisnake_client::room_state::RoomState;
 use multisnake_shared::SnakeMessage;
-use room_state::RoomState;
+use multisnake_shared::controller::BotLevel;
+use std::path::PathBuf;
+use std::sync::mpsc::TryRecvError;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use tui::{RoomChoice, RoomTarget};
 
 const BACK_TUI_DELAY_MS: u64 = 3000;
 
 #[derive(Parser)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[arg(default_value = "127.0.0.1:4040")]
     server_addr: String,
+    /// Name shown to other players and in the server's status routes.
+    #[arg(long)]
+    name: Option<String>,
+    /// Log in to the account `--name`, registered names cannot be used without it.
+    #[arg(
+        long,
+        env = "MULTISNAKE_PASSWORD",
+        requires = "name",
+        hide_env_values = true
+    )]
+    password: Option<String>,
+    /// Register the account `--name` with `--password` before logging in.
+    #[arg(long, requires = "password")]
+    register: bool,
+    /// Where logs are written, the terminal is taken by the room selector.
+    #[arg(long, default_value = "multisnake_client.log")]
+    log_file: String,
+    /// Log filter, e.g. `debug` or `multisnake_client=trace` (`RUST_LOG` overrides it).
+    #[arg(long, default_value = "info")]
+    log_level: String,
+    /// Bots playing along in offline games.
+    #[arg(long, default_value = "3")]
+    offline_bots: usize,
+    /// How well the offline bots play.
+    #[arg(long, value_enum, default_value = "lookahead")]
+    offline_bot_level: BotLevel,
+    /// Tick duration of offline games.
+    #[arg(long, default_value = "100")]
+    offline_tick_duration_ms: u32,
+    /// Seed of the offline food and spawns, random otherwise. The seed is logged.
+    #[arg(long)]
+    offline_seed: Option<u64>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Plays back a replay file recorded by the server's `--record-dir`.
+    Replay { file: PathBuf },
 }
 
 #[macroquad::main(window_conf)]
 async fn main() {
     let args = Args::parse();
+    logging::init(&args.log_file, &args.log_level);
+    if let Some(Command::Replay { file }) = &args.command {
+        if let Err(err) = playback::run(file).await {
+            error!(path = %file.display(), "Cannot play the replay: {}", err);
+            eprintln!("Cannot play {}: {}", file.display(), err);
+        }
+        return;
+    }
+    // Shown in the TUI when a game ended for some other reason than dying.
+    let mut notice: Option<String> = None;
+
+    // Token of the account the rooms are joined with.
+    let mut auth = None;
+    // As the server knows it, quick play looks up its rating.
+    let mut player_name = args.name.clone();
+    if let (Some(name), Some(password)) = (&args.name, &args.password) {
+        match account::login(&args.server_addr, name, password, args.register) {
+            Ok(login) => {
+                info!(name = login.name, "Logged in.");
+                notice = Some(format!("Logged in as {}.", login.name));
+                auth = Some(login.token.to_string());
+                player_name = Some(login.name);
+            }
+            Err(reason) => {
+                error!(name, "Could not log in: {}", reason);
+                eprintln!("Could not log in as {}: {}", name, reason);
+                return;
+            }
+        }
+    }
+
+    let mut keyboard = input::Keyboard::new();
 
     loop {
         let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
 
         let (tui_tx, mut tui_rx) = mpsc::unbounded_channel();
         let server_addr_clone = args.server_addr.clone();
+        let tui_notice = notice.take();
+        let tui_name = player_name.clone();
 
         // Spawn TUI in a separate task
         tokio_runtime.spawn(async move {
-            let result = tui::run_room_selector(&server_addr_clone).await.unwrap();
+            let result = tui::run_room_selector(&server_addr_clone, tui_notice, tui_name)
+                .await
+                .unwrap_or_else(|err| {
+                    error!("TUI error: {}", err);
+                    None
+                });
             let _ = tui_tx.send(result);
         });
 
@@ -45,7 +133,7 @@ async fn main() {
                         break;
                     }
                     None => {
-                        println!("Exiting...");
+                        info!("Exiting...");
                         return;
                     }
                 }
@@ -65,44 +153,111 @@ async fn main() {
         let (from_client_tx, from_client_rx) = mpsc::unbounded_channel::<SnakeMessage>();
         let (from_server_tx, from_server_rx) = std::sync::mpsc::channel();
 
-        let server_addr = args.server_addr.clone();
+        let room_path = selected_room.path();
 
-        tokio_runtime.spawn(async move {
-            room_connection::run(
-                format!("ws://{}/room/{}", server_addr, selected_room),
-                from_client_rx,
-                from_server_tx,
-            )
-            .await;
-        });
+        let connection = if let RoomTarget::Offline = selected_room.target {
+            let config = OfflineConfig {
+                tick_duration_ms: args.offline_tick_duration_ms,
+                bots: args.offline_bots,
+                bot_level: args.offline_bot_level,
+                seed: args.offline_seed,
+            };
+            tokio_runtime.spawn(offline::run(config, from_client_rx, from_server_tx))
+        } else {
+            let url = room_url(
+                &args.server_addr,
+                &selected_room,
+                args.name.as_deref(),
+                auth.as_deref(),
+            );
+            tokio_runtime.spawn(room_connection::run(url, from_client_rx, from_server_tx))
+        };
+
+        // Wait for the room to answer, the window keeps drawing meanwhile.
+        let joined = loop {
+            match from_server_rx.try_recv() {
+                Ok(msg) => break Some(msg),
+                Err(TryRecvError::Disconnected) => break None,
+                Err(TryRecvError::Empty) => {}
+            }
+            clear_background(BLACK);
+            draw_text("Connecting...", 20.0, 30.0, 30.0, WHITE);
+            next_frame().await;
+        };
 
-        let Ok(SnakeMessage::OnJoin {
+        let Some(SnakeMessage::OnJoin {
             my_id,
             snakes,
             tick_duration_ms,
+            input_queue_depth,
             food,
-        }) = from_server_rx.recv()
+            tick,
+            ..
+        }) = joined
         else {
-            eprintln!("Failed to receive OnJoin message from server");
+            warn!(
+                room = room_path,
+                "Failed to receive OnJoin message from server"
+            );
+            // The connection task is done by now, it may know why.
+            let refusal = tokio_runtime.block_on(connection).ok().flatten();
+            notice = Some(refusal.unwrap_or_else(|| "Could not join the room.".to_string()));
             continue;
         };
 
-        let mut room_state = RoomState::new(my_id, snakes, tick_duration_ms, food);
+        info!(room = room_path, %my_id, tick, "Joined room.");
+        let mut room_state = RoomState::new(
+            my_id,
+            snakes,
+            tick_duration_ms,
+            input_queue_depth,
+            food,
+            tick,
+        );
         let mut death_time: Option<Instant> = None;
+        // Keys pressed in the room selector are not turns.
+        keyboard.pressed_directions();
 
         loop {
-            if room_state.alive
-                && let Some((dx, dy)) = room_state.handle_input()
-            {
-                let _ = from_client_tx.send(SnakeMessage::MoveIntent { dx, dy });
+            let turns = keyboard.pressed_directions();
+            if room_state.alive {
+                for dir in turns {
+                    if room_state.steer(dir) {
+                        let _ = from_client_tx.send(SnakeMessage::MoveIntent {
+                            dir,
+                            tick: room_state.server_tick,
+                        });
+                    }
+                }
             }
 
             // Process incoming messages from server
-            while let Ok(msg) = from_server_rx.try_recv() {
-                if let SnakeMessage::TickUpdate { .. } = msg {
-                    room_state.snapshot_state();
+            let mut connection_lost = false;
+            loop {
+                match from_server_rx.try_recv() {
+                    Ok(msg) => {
+                        if let SnakeMessage::TickUpdate { .. } = msg {
+                            room_state.snapshot_state();
+                        }
+                        room_state.process_message(msg);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        connection_lost = true;
+                        break;
+                    }
                 }
-                room_state.process_message(msg);
+            }
+
+            // The connection task gave up reconnecting, or the server shut down.
+            // It also ends after our death, the game finished screen handles that.
+            if connection_lost && room_state.alive {
+                info!("Connection to the room ended.");
+                notice = Some(match (&room_state.kicked, &room_state.shutdown_notice) {
+                    (Some(reason), _) | (None, Some((reason, _))) => reason.clone(),
+                    (None, None) => "Lost connection to the server.".to_string(),
+                });
+                break;
             }
 
             // Drawing
@@ -114,6 +269,7 @@ async fn main() {
                     }
                     Some(time) => {
                         if time.elapsed() >= Duration::from_millis(BACK_TUI_DELAY_MS) {
+                            notice = room_state.kicked.clone();
                             break;
                         }
                     }
@@ -127,7 +283,7 @@ async fn main() {
 
             let elapsed = room_state.last_update_time.elapsed().as_millis();
 
-            let interpol_t = (elapsed as f32 / room_state.tick_duration_ms as f32).min(1.0);
+            let interpol_t = (elapsed as f32 / room_state.tick_ms()).min(1.0);
 
             draw::draw_snake(
                 &room_state.my_snake.segments,
@@ -149,12 +305,44 @@ async fn main() {
                 );
             }
             draw::draw_food(room_state.food);
+            draw::draw_scoreboard(&room_state.scoreboard, room_state.my_id);
+            draw::draw_ping(room_state.my_rtt_ms());
+            if let Some((reason, deadline)) = &room_state.shutdown_notice {
+                let seconds_left = deadline.saturating_duration_since(Instant::now()).as_secs();
+                draw::draw_shutdown_notice(reason, seconds_left);
+            }
+            if let Some((message, until)) = &room_state.server_notice
+                && Instant::now() < *until
+            {
+                draw::draw_server_notice(message);
+            }
 
             next_frame().await;
         }
     }
 }
 
+fn room_url(
+    server_addr: &str,
+    choice: &RoomChoice,
+    name: Option<&str>,
+    auth: Option<&str>,
+) -> String {
+    let url = format!("ws://{}{}", server_addr, choice.path());
+    let params: Vec<(&str, &str)> = [
+        ("name", name),
+        ("password", choice.password.as_deref()),
+        ("auth", auth),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect();
+    match serde_urlencoded::to_string(params) {
+        Ok(query) if !query.is_empty() => format!("{}?{}", url, query),
+        _ => url,
+    }
+}
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "multisnake".to_string(),
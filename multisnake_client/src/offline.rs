@@ -0,0 +1,116 @@
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use multisnake_shared::controller::{BotLevel, Bots};
+use multisnake_shared::simulation::{Event, Input, Simulation};
+use multisnake_shared::{ScoreEntry, SnakeMessage};
+
+// Same as the server's default.
+const INPUT_QUEUE_DEPTH: u32 = 3;
+const SCOREBOARD_INTERVAL_TICKS: u64 = 10;
+
+pub struct OfflineConfig {
+    pub tick_duration_ms: u32,
+    // Bots kept in the room, one replaces each bot that dies.
+    pub bots: usize,
+    pub bot_level: BotLevel,
+    // Seed of the food and spawns, random when `None`.
+    pub seed: Option<u64>,
+}
+
+/// Plays a room in-process, talking to the game through the same channels as
+/// `room_connection::run` so that it cannot tell the difference.
+/// Ends when the player's snake dies or the game hangs up.
+pub async fn run(
+    config: OfflineConfig,
+    mut from_client_rx: UnboundedReceiver<SnakeMessage>,
+    from_server_tx: std::sync::mpsc::Sender<SnakeMessage>,
+) -> Option<String> {
+    let tick_duration_ms = config.tick_duration_ms.max(1);
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let mut sim = Simulation::new(tick_duration_ms, INPUT_QUEUE_DEPTH, seed);
+    let mut bots = Bots::default();
+    let my_id = Uuid::new_v4();
+    sim.apply(&Input::Join { id: my_id });
+    fill_bots(&mut sim, &mut bots, &config);
+    info!(
+        bots = config.bots,
+        level = config.bot_level.as_str(),
+        seed,
+        "Playing offline."
+    );
+
+    let joined = SnakeMessage::OnJoin {
+        my_id,
+        session_token: Uuid::nil(),
+        snakes: sim.bodies(),
+        food: sim.food,
+        tick_duration_ms,
+        input_queue_depth: INPUT_QUEUE_DEPTH,
+        tick: sim.tick_count,
+    };
+    if from_server_tx.send(joined).is_err() {
+        return None;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_millis(tick_duration_ms as u64));
+    interval.tick().await;
+    let mut inputs = Vec::new();
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                fill_bots(&mut sim, &mut bots, &config);
+                inputs.extend(bots.steer(&sim));
+                let events = sim.step(&inputs);
+                inputs.clear();
+
+                let mut died = false;
+                for event in &events {
+                    if let Event::Died { id, .. } = event {
+                        bots.remove(id);
+                        died |= *id == my_id;
+                    }
+                }
+                if from_server_tx.send(sim.tick_update(&events)).is_err() {
+                    return None;
+                }
+                if died {
+                    debug!(tick = sim.tick_count, "The offline snake died.");
+                    return None;
+                }
+
+                if sim.tick_count.is_multiple_of(SCOREBOARD_INTERVAL_TICKS) {
+                    let entries = sim
+                        .snakes
+                        .iter()
+                        .map(|(id, snake)| ScoreEntry {
+                            id: *id,
+                            length: snake.body.len(),
+                            rtt_ms: None,
+                            bot: bots.contains(id),
+                            rating: None,
+                        })
+                        .collect();
+                    let _ = from_server_tx.send(SnakeMessage::Scoreboard { entries });
+                }
+            }
+            maybe_msg = from_client_rx.recv() => match maybe_msg {
+                Some(SnakeMessage::MoveIntent { dir, .. }) => {
+                    inputs.push(Input::Turn { id: my_id, dir });
+                }
+                Some(_) => {}
+                None => return None,
+            },
+        }
+    }
+}
+
+fn fill_bots(sim: &mut Simulation, bots: &mut Bots, config: &OfflineConfig) {
+    for _ in bots.len()..config.bots {
+        let id = Uuid::new_v4();
+        sim.apply(&Input::Join { id });
+        bots.insert(id, config.bot_level.controller());
+    }
+}
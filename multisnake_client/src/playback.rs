@@ -0,0 +1,113 @@
+use macroquad::prelude::*;
+use std::io;
+use std::path::Path;
+use tracing::info;
+
+use multisnake_client::replay::ReplayPlayer;
+
+use crate::draw;
+
+// How far the arrow keys seek.
+const SEEK_SECS: u64 = 10;
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 16.0;
+
+/// Shows a replay file until the window is closed or Escape/Q is pressed.
+pub async fn run(path: &Path) -> io::Result<()> {
+    let mut player = ReplayPlayer::open(path)?;
+    info!(
+        path = %path.display(),
+        room_id = player.room_id,
+        seed = player.seed,
+        first_tick = player.first_tick,
+        last_tick = player.last_tick,
+        "Playing a replay."
+    );
+    let tick_ms = player.tick_duration_ms.max(1) as f32;
+    let seek_ticks = SEEK_SECS * 1000 / player.tick_duration_ms.max(1) as u64;
+    let mut paused = false;
+    let mut speed: f32 = 1.0;
+    // Time played since the last tick, in replay milliseconds.
+    let mut elapsed_ms = 0.0;
+
+    loop {
+        if is_key_pressed(KeyCode::Escape) || is_key_pressed(KeyCode::Q) {
+            return Ok(());
+        }
+        if is_key_pressed(KeyCode::Space) {
+            if player.at_end() {
+                player.seek(player.first_tick);
+            }
+            paused = !paused;
+        }
+        if is_key_pressed(KeyCode::Right) {
+            player.seek(player.tick + seek_ticks);
+            elapsed_ms = 0.0;
+        }
+        if is_key_pressed(KeyCode::Left) {
+            player.seek(player.tick.saturating_sub(seek_ticks));
+            elapsed_ms = 0.0;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            speed = (speed * 2.0).min(MAX_SPEED);
+        }
+        if is_key_pressed(KeyCode::Down) {
+            speed = (speed / 2.0).max(MIN_SPEED);
+        }
+        if is_key_pressed(KeyCode::Tab) {
+            player.follow_next();
+        }
+
+        if !paused {
+            elapsed_ms += get_frame_time() * 1000.0 * speed;
+            while elapsed_ms >= tick_ms {
+                elapsed_ms -= tick_ms;
+                if !player.step() {
+                    paused = true;
+                    elapsed_ms = 0.0;
+                }
+            }
+        }
+
+        let state = &player.state;
+        clear_background(BLACK);
+        draw::draw_grid();
+        let interpol_t = (elapsed_ms / tick_ms).min(1.0);
+        draw::draw_snake(
+            &state.my_snake.segments,
+            state.prev_my_snake.as_ref(),
+            interpol_t,
+            true,
+            state.ghosts.contains(&state.my_id),
+        );
+        for (id, snake) in state.other_snakes.iter() {
+            draw::draw_snake(
+                &snake.segments,
+                state.prev_other_snakes.get(id),
+                interpol_t,
+                false,
+                state.ghosts.contains(id),
+            );
+        }
+        draw::draw_food(state.food);
+        draw::draw_scoreboard(&state.scoreboard, state.my_id);
+
+        let status = format!(
+            "tick {}/{}  x{}{}  {}",
+            player.tick,
+            player.last_tick,
+            speed,
+            if player.at_end() {
+                "  end"
+            } else if paused {
+                "  paused"
+            } else {
+                ""
+            },
+            player.followed_name().unwrap_or_default()
+        );
+        draw::draw_replay_status(&status);
+
+        next_frame().await;
+    }
+}
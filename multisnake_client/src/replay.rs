@@ -0,0 +1,261 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+use multisnake_shared::replay::{self, ReplayRecord};
+use multisnake_shared::{GameMode, Pos, ScoreEntry, SnakeMessage};
+
+use crate::room_state::RoomState;
+
+/// Plays a recorded room back through a `RoomState`, as if connected to it
+/// with the followed snake's id. Knows nothing of time, the caller steps it.
+pub struct ReplayPlayer {
+    pub room_id: u32,
+    pub mode: GameMode,
+    pub tick_duration_ms: u32,
+    pub seed: u64,
+    // Records after the header.
+    records: Vec<ReplayRecord>,
+    // Index in `records` of each keyframe, by tick.
+    keyframes: Vec<(u64, usize)>,
+    // Next record to play.
+    cursor: usize,
+    pub state: RoomState,
+    pub tick: u64,
+    pub first_tick: u64,
+    pub last_tick: u64,
+    pub names: HashMap<Uuid, String>,
+}
+
+impl ReplayPlayer {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut records = replay::read(path)?.into_iter();
+        let Some(ReplayRecord::Header {
+            room_id,
+            mode,
+            tick_duration_ms,
+            seed,
+            ..
+        }) = records.next()
+        else {
+            return Err(invalid("the file does not start with a header"));
+        };
+        let records: Vec<ReplayRecord> = records.collect();
+
+        let keyframes: Vec<(u64, usize)> = records
+            .iter()
+            .enumerate()
+            .filter_map(|(i, record)| match record {
+                ReplayRecord::Keyframe { tick, .. } => Some((*tick, i)),
+                _ => None,
+            })
+            .collect();
+        let Some(&(first_tick, _)) = keyframes.first() else {
+            return Err(invalid("the file has no keyframe"));
+        };
+        let last_tick = records
+            .iter()
+            .rev()
+            .find_map(record_tick)
+            .unwrap_or(first_tick)
+            .max(first_tick);
+
+        let mut player = Self {
+            room_id,
+            mode,
+            tick_duration_ms,
+            seed,
+            records,
+            keyframes,
+            cursor: 0,
+            state: RoomState::new(
+                Uuid::nil(),
+                HashMap::new(),
+                tick_duration_ms,
+                1,
+                Pos { x: 0, y: 0 },
+                first_tick,
+            ),
+            tick: first_tick,
+            first_tick,
+            last_tick,
+            names: HashMap::new(),
+        };
+        player.seek(first_tick);
+        Ok(player)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.cursor >= self.records.len()
+    }
+
+    /// Plays the next tick. Returns `false` at the end of the file.
+    pub fn step(&mut self) -> bool {
+        while let Some(record) = self.records.get(self.cursor) {
+            self.cursor += 1;
+            match record {
+                ReplayRecord::Tick { update, names, .. } => {
+                    if let Some(tick) = record_tick(record) {
+                        self.tick = tick;
+                    }
+                    self.names
+                        .extend(names.iter().map(|(id, name)| (*id, name.clone())));
+                    self.state.snapshot_state();
+                    self.state.process_message(update.clone());
+                    self.keep_following();
+                    self.update_scoreboard();
+                    return true;
+                }
+                // Only needed to seek.
+                ReplayRecord::Keyframe { .. } | ReplayRecord::Header { .. } => {}
+            }
+        }
+        false
+    }
+
+    /// Jumps to `tick`, from the closest keyframe before it.
+    pub fn seek(&mut self, tick: u64) {
+        let tick = tick.clamp(self.first_tick, self.last_tick);
+        let index = self
+            .keyframes
+            .partition_point(|(keyframe_tick, _)| *keyframe_tick <= tick)
+            .max(1)
+            - 1;
+        let (_, record_index) = self.keyframes[index];
+        let ReplayRecord::Keyframe {
+            tick: keyframe_tick,
+            snakes,
+            food,
+            ghosts,
+            names,
+        } = &self.records[record_index]
+        else {
+            return;
+        };
+
+        // Keeps following the same snake if it is there.
+        let followed = if snakes.contains_key(&self.state.my_id) {
+            self.state.my_id
+        } else {
+            Uuid::nil()
+        };
+        self.tick = *keyframe_tick;
+        self.names = names.clone();
+        self.load(followed, snakes.clone(), *food, ghosts.clone());
+        self.cursor = record_index + 1;
+        self.keep_following();
+        self.update_scoreboard();
+
+        while self.tick < tick && self.step() {}
+        // Nothing to move from after a jump.
+        self.state.snapshot_state();
+    }
+
+    /// Follows the next snake in the room, by id.
+    pub fn follow_next(&mut self) {
+        let mut ids: Vec<Uuid> = self.snakes().into_keys().collect();
+        ids.sort();
+        let next = ids
+            .iter()
+            .find(|id| **id > self.state.my_id)
+            .or(ids.first())
+            .copied();
+        if let Some(id) = next {
+            self.follow(id);
+        }
+    }
+
+    /// Name of the followed snake, or its id without one.
+    pub fn followed_name(&self) -> Option<String> {
+        let id = self.state.my_id;
+        if id.is_nil() {
+            return None;
+        }
+        Some(
+            self.names
+                .get(&id)
+                .cloned()
+                .unwrap_or_else(|| id.to_string()),
+        )
+    }
+
+    /// Every snake of the room, the followed one included.
+    pub fn snakes(&self) -> HashMap<Uuid, VecDeque<Pos>> {
+        let mut snakes: HashMap<Uuid, VecDeque<Pos>> = self
+            .state
+            .other_snakes
+            .iter()
+            .map(|(id, snake)| (*id, snake.segments.clone()))
+            .collect();
+        if self.state.alive && !self.state.my_snake.segments.is_empty() {
+            snakes.insert(self.state.my_id, self.state.my_snake.segments.clone());
+        }
+        snakes
+    }
+
+    fn follow(&mut self, id: Uuid) {
+        let snakes = self.snakes();
+        let ghosts = std::mem::take(&mut self.state.ghosts);
+        self.load(id, snakes, self.state.food, ghosts);
+        self.update_scoreboard();
+    }
+
+    /// Switches to the longest snake once the followed one died.
+    fn keep_following(&mut self) {
+        if self.state.alive && !self.state.my_snake.segments.is_empty() {
+            return;
+        }
+        let longest = self
+            .state
+            .other_snakes
+            .iter()
+            .max_by_key(|(id, snake)| (snake.segments.len(), **id))
+            .map(|(id, _)| *id);
+        if longest.is_some() || !self.state.alive {
+            self.follow(longest.unwrap_or(Uuid::nil()));
+        }
+    }
+
+    fn load(
+        &mut self,
+        followed: Uuid,
+        snakes: HashMap<Uuid, VecDeque<Pos>>,
+        food: Pos,
+        ghosts: Vec<Uuid>,
+    ) {
+        self.state = RoomState::new(followed, snakes, self.tick_duration_ms, 1, food, self.tick);
+        self.state.ghosts = ghosts;
+    }
+
+    /// The scoreboard the server would have sent, no one has a ping.
+    fn update_scoreboard(&mut self) {
+        let mut entries: Vec<ScoreEntry> = self
+            .snakes()
+            .into_iter()
+            .map(|(id, segments)| ScoreEntry {
+                id,
+                length: segments.len(),
+                rtt_ms: None,
+                bot: false,
+                rating: None,
+            })
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.length));
+        self.state.scoreboard = entries;
+    }
+}
+
+fn record_tick(record: &ReplayRecord) -> Option<u64> {
+    match record {
+        ReplayRecord::Tick {
+            update: SnakeMessage::TickUpdate { tick, .. },
+            ..
+        } => Some(*tick),
+        _ => None,
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
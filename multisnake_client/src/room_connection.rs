@@ -1,18 +1,124 @@
 use futures_util::{SinkExt, StreamExt};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{self, Message},
+};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
 use multisnake_shared::SnakeMessage;
 
+const INITIAL_BACKOFF_MS: u64 = 250;
+const MAX_BACKOFF_MS: u64 = 5000;
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+
+/// What survives from one connection attempt to the next.
+#[derive(Default)]
+struct Session {
+    my_id: Option<Uuid>,
+    token: Option<Uuid>,
+    // The server announced it is going down, there is nothing to reconnect to.
+    server_shutdown: bool,
+    // The server kicked us out, reconnecting would only get the same answer.
+    kicked: bool,
+}
+
+enum SessionEnd {
+    /// The game side hung up, there is nobody to reconnect for.
+    Closed,
+    /// The connection to the server was lost.
+    Dropped,
+}
+
+/// Keeps the game connected to `url`, reconnecting with exponential backoff
+/// and reclaiming the same snake with the session token from `OnJoin`.
+/// Returns the server's answer if it refused the connection, or why the first
+/// connection failed: there is no snake to reconnect to before joining.
 pub async fn run(
     url: String,
     mut from_client_rx: tokio::sync::mpsc::UnboundedReceiver<SnakeMessage>,
     from_server_tx: std::sync::mpsc::Sender<SnakeMessage>,
-) {
-    let (ws_stream, _) = match connect_async(&url).await {
-        Ok(ws) => ws,
-        Err(_) => return,
-    };
+) -> Option<String> {
+    let mut session = Session::default();
+    // The query may hold a password, keep it out of the logs.
+    let log_url = url.split('?').next().unwrap_or_default().to_string();
+    let mut attempts = 0;
+
+    loop {
+        let attempt_url = match session.token {
+            // `url` may already carry a query, e.g. the player's name.
+            Some(token) if url.contains('?') => format!("{}&token={}", url, token),
+            Some(token) => format!("{}?token={}", url, token),
+            None => url.clone(),
+        };
 
+        match connect_async(&attempt_url).await {
+            Ok((ws_stream, _)) => {
+                info!(url = %log_url, reconnect = session.token.is_some(), "Connected.");
+                attempts = 0;
+                let end = run_session(
+                    ws_stream,
+                    &mut from_client_rx,
+                    &from_server_tx,
+                    &mut session,
+                )
+                .await;
+                if let SessionEnd::Closed = end {
+                    debug!("Session closed.");
+                    return None;
+                }
+                if session.server_shutdown {
+                    info!("The server shut down, not reconnecting.");
+                    return None;
+                }
+                if session.kicked {
+                    info!("Kicked by the server, not reconnecting.");
+                    return None;
+                }
+                if session.token.is_none() {
+                    warn!("Lost the connection to the server before joining.");
+                    return None;
+                }
+                warn!("Lost the connection to the server.");
+            }
+            // Wrong password, ban, unknown invite code or shutdown: retrying will not help.
+            Err(tungstenite::Error::Http(response)) => {
+                let status = response.status();
+                let reason = response
+                    .body()
+                    .as_ref()
+                    .and_then(|body| String::from_utf8(body.clone()).ok())
+                    .filter(|body| !body.is_empty())
+                    .unwrap_or_else(|| status.to_string());
+                warn!(%status, "The server refused the connection: {}", reason);
+                return Some(reason);
+            }
+            Err(err) if session.token.is_none() => {
+                warn!(url = %log_url, "Could not connect: {}", err);
+                return Some(format!("Could not connect to the server: {}", err));
+            }
+            Err(err) => warn!(url = %log_url, attempts, "Could not connect: {}", err),
+        }
+
+        attempts += 1;
+        if attempts > MAX_RECONNECT_ATTEMPTS {
+            warn!("Giving up after {} attempts.", MAX_RECONNECT_ATTEMPTS);
+            return None;
+        }
+        let backoff_ms = (INITIAL_BACKOFF_MS << (attempts - 1)).min(MAX_BACKOFF_MS);
+        debug!(backoff_ms, "Reconnecting.");
+        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+    }
+}
+
+async fn run_session(
+    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    from_client_rx: &mut tokio::sync::mpsc::UnboundedReceiver<SnakeMessage>,
+    from_server_tx: &std::sync::mpsc::Sender<SnakeMessage>,
+    session: &mut Session,
+) -> SessionEnd {
     let (mut ws_tx, mut ws_rx) = ws_stream.split();
 
     loop {
@@ -22,25 +128,71 @@ pub async fn run(
                     Some(msg) => {
                         let json = serde_json::to_string(&msg).unwrap();
                         if ws_tx.send(Message::Text(json.into())).await.is_err() {
-                            break;
+                            return SessionEnd::Dropped;
                         }
                     }
                     None => {
                         let _ = ws_tx.close().await;
-                        break;
+                        return SessionEnd::Closed;
                     }
                 }
             },
             server_msg = ws_rx.next() => {
                 match server_msg {
                     Some(Ok(Message::Text(txt))) => {
-                        if let Ok(parsed) = serde_json::from_str::<SnakeMessage>(&txt)
-                            && from_server_tx.send(parsed).is_err()
-                        {
-                            break;
+                        let parsed = match serde_json::from_str::<SnakeMessage>(&txt) {
+                            Ok(parsed) => parsed,
+                            Err(err) => {
+                                warn!("Could not decode a server message: {}", err);
+                                continue;
+                            }
+                        };
+
+                        let mut died = false;
+                        match &parsed {
+                            // Heartbeats are answered here and never reach the game.
+                            SnakeMessage::Ping { seq } => {
+                                let json = serde_json::to_string(&SnakeMessage::Pong { seq: *seq }).unwrap();
+                                if ws_tx.send(Message::Text(json.into())).await.is_err() {
+                                    return SessionEnd::Dropped;
+                                }
+                                continue;
+                            }
+                            SnakeMessage::OnJoin { my_id, session_token, .. } => {
+                                session.my_id = Some(*my_id);
+                                session.token = Some(*session_token);
+                            }
+                            SnakeMessage::TickUpdate { deaths, .. } => {
+                                died = session.my_id.is_some_and(|id| deaths.contains(&id));
+                            }
+                            SnakeMessage::Kicked { .. } => {
+                                session.kicked = true;
+                            }
+                            SnakeMessage::ServerShutdown { reason, seconds } => {
+                                info!(seconds, "Server shutdown announced: {}", reason);
+                                session.server_shutdown = true;
+                            }
+                            _ => {}
                         }
+
+                        if from_server_tx.send(parsed).is_err() {
+                            return SessionEnd::Closed;
+                        }
+                        // There is no snake left to reconnect to.
+                        if died {
+                            let _ = ws_tx.close().await;
+                            return SessionEnd::Closed;
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        debug!(?frame, "Server closed the connection.");
+                        return SessionEnd::Dropped;
+                    }
+                    Some(Err(err)) => {
+                        debug!("Socket error: {}", err);
+                        return SessionEnd::Dropped;
                     }
-                    Some(Ok(Message::Close(_))) | None => break,
+                    None => return SessionEnd::Dropped,
                     _ => {}
 
                 }
@@ -1,11 +1,16 @@
-use macroquad::prelude::{KeyCode, is_key_pressed};
 use std::{
     collections::{HashMap, VecDeque},
-    time::Instant,
+    time::{Duration, Instant},
 };
+use tracing::{debug, info};
 use uuid::Uuid;
 
-use multisnake_shared::{Pos, SnakeMessage};
+use multisnake_shared::{Direction, Pos, ScoreEntry, SnakeMessage, rules};
+
+// Upper bound on how far the local snake may run ahead of the server.
+const MAX_PREDICTED_TICKS: u32 = 5;
+// How long a message from the server operators stays on screen.
+const SERVER_NOTICE_DURATION: Duration = Duration::from_secs(5);
 
 pub struct Snake {
     pub segments: VecDeque<Pos>,
@@ -20,36 +25,54 @@ impl Snake {
         }
     }
 
-    pub fn apply_move(&mut self, dx: i32, dy: i32, growing: bool) {
-        if let Some(head) = self.segments.front() {
-            let new_head = Pos {
-                x: head.x + dx,
-                y: head.y + dy,
-            };
-
-            self.segments.push_front(new_head);
-
-            if growing {
-                self.growing = false;
-            } else {
-                self.segments.pop_back();
-            }
+    pub fn apply_move(&mut self, dir: Direction, growing: bool) {
+        if let Some(&head) = self.segments.front() {
+            rules::advance(&mut self.segments, rules::next_head(head, dir), growing);
+            self.growing = false;
         }
     }
 }
 
+/// A move of the local snake simulated ahead of the server.
+pub struct PredictedStep {
+    pub tick: u64,
+    pub dir: Direction,
+    pub grew: bool,
+    // Queued turn this step used up, handed back if the step gets rolled back.
+    pub turn: Option<Direction>,
+}
+
 pub struct RoomState {
     pub my_id: Uuid,
+    // What gets drawn: `confirmed_snake` with `predicted_steps` applied on top.
     pub my_snake: Snake,
+    // The local snake as of `server_tick`.
+    pub confirmed_snake: VecDeque<Pos>,
+    pub server_tick: u64,
+    pub predicted_steps: VecDeque<PredictedStep>,
+    // Turns requested by the player that no predicted step has used yet.
+    pub pending_turns: VecDeque<Direction>,
+    pub input_queue_depth: u32,
     pub other_snakes: HashMap<Uuid, Snake>,
     pub alive: bool,
     pub food: Pos,
     pub ghosts: Vec<Uuid>,
+    pub scoreboard: Vec<ScoreEntry>,
 
     pub prev_my_snake: Option<VecDeque<Pos>>,
     pub prev_other_snakes: HashMap<uuid::Uuid, VecDeque<Pos>>,
     pub last_update_time: Instant,
     pub tick_duration_ms: u32,
+    // Tick interval the server actually achieves, once it reported it.
+    pub measured_tick_ms: Option<f32>,
+    // Reason and deadline of an announced server shutdown.
+    pub shutdown_notice: Option<(String, Instant)>,
+    // Message from the server operators and until when it is shown.
+    pub server_notice: Option<(String, Instant)>,
+    // Why the server kicked us out, if it did.
+    pub kicked: Option<String>,
+    // Predicted steps the server did differently.
+    pub mispredictions: u64,
 }
 
 impl RoomState {
@@ -57,7 +80,9 @@ impl RoomState {
         my_id: Uuid,
         snakes: HashMap<Uuid, VecDeque<Pos>>,
         tick_duration_ms: u32,
+        input_queue_depth: u32,
         food: Pos,
+        tick: u64,
     ) -> Self {
         let mut my_snake = Snake::new(VecDeque::new());
         let mut other_snakes = HashMap::new();
@@ -72,38 +97,56 @@ impl RoomState {
 
         Self {
             my_id,
+            confirmed_snake: my_snake.segments.clone(),
             my_snake,
+            server_tick: tick,
+            predicted_steps: VecDeque::new(),
+            pending_turns: VecDeque::new(),
+            input_queue_depth,
             other_snakes,
             alive: true,
             food,
             ghosts: Vec::new(),
+            scoreboard: Vec::new(),
 
             prev_my_snake: None,
             prev_other_snakes: HashMap::new(),
             last_update_time: Instant::now(),
 
             tick_duration_ms,
-        }
-    }
-
-    pub fn handle_input(&self) -> Option<(i32, i32)> {
-        if is_key_pressed(KeyCode::Up) || is_key_pressed(KeyCode::W) {
-            Some((0, -1))
-        } else if is_key_pressed(KeyCode::Down) || is_key_pressed(KeyCode::S) {
-            Some((0, 1))
-        } else if is_key_pressed(KeyCode::Left) || is_key_pressed(KeyCode::A) {
-            Some((-1, 0))
-        } else if is_key_pressed(KeyCode::Right) || is_key_pressed(KeyCode::D) {
-            Some((1, 0))
-        } else {
-            None
+            measured_tick_ms: None,
+            shutdown_notice: None,
+            server_notice: None,
+            kicked: None,
+            mispredictions: 0,
         }
     }
 
     pub fn process_message(&mut self, msg: SnakeMessage) {
         match msg {
-            SnakeMessage::OnJoin { .. } => {}
+            // Sent again after a reconnect, the snapshot replaces everything we had.
+            SnakeMessage::OnJoin {
+                my_id,
+                snakes,
+                tick_duration_ms,
+                input_queue_depth,
+                food,
+                tick,
+                ..
+            } => {
+                let mispredictions = self.mispredictions;
+                *self = RoomState::new(
+                    my_id,
+                    snakes,
+                    tick_duration_ms,
+                    input_queue_depth,
+                    food,
+                    tick,
+                );
+                self.mispredictions = mispredictions;
+            }
             SnakeMessage::TickUpdate {
+                tick,
                 moves,
                 food,
                 deaths,
@@ -111,6 +154,11 @@ impl RoomState {
                 new_snakes,
                 ghosts,
             } => {
+                // Already covered by the last snapshot.
+                if tick <= self.server_tick {
+                    return;
+                }
+
                 self.food = food;
 
                 // Add new clients snakes
@@ -123,29 +171,169 @@ impl RoomState {
                 // Process deaths
                 for id in deaths {
                     if id == self.my_id {
-                        println!("You died!");
+                        info!(tick = self.server_tick, "You died!");
                         self.alive = false;
                     }
                     self.other_snakes.remove(&id);
                 }
 
                 // Process moves
-                for (id, (dx, dy)) in moves {
+                let mut my_move = None;
+                for (id, dir) in moves {
                     let growing = eaters.contains(&id);
 
                     if id == self.my_id {
-                        self.my_snake.apply_move(dx, dy, growing);
+                        let head = *self.confirmed_snake.front().unwrap();
+                        rules::advance(
+                            &mut self.confirmed_snake,
+                            rules::next_head(head, dir),
+                            growing,
+                        );
+                        my_move = Some(dir);
                     } else if let Some(snake) = self.other_snakes.get_mut(&id) {
-                        snake.apply_move(dx, dy, growing);
+                        snake.apply_move(dir, growing);
                     }
                 }
 
                 self.ghosts = ghosts;
+
+                if self.alive {
+                    self.reconcile(tick, my_move, eaters.contains(&self.my_id));
+                }
+            }
+            SnakeMessage::ServerShutdown { reason, seconds } => {
+                let deadline = Instant::now() + Duration::from_secs(seconds as u64);
+                self.shutdown_notice = Some((reason, deadline));
+            }
+            SnakeMessage::ServerNotice { message } => {
+                self.server_notice = Some((message, Instant::now() + SERVER_NOTICE_DURATION));
+            }
+            SnakeMessage::Kicked { reason } => {
+                info!("Kicked: {}", reason);
+                self.kicked = Some(reason);
+            }
+            SnakeMessage::TickRate { measured_tick_ms } => {
+                self.measured_tick_ms = Some(measured_tick_ms);
+            }
+            SnakeMessage::Scoreboard { mut entries } => {
+                entries.sort_by_key(|entry| std::cmp::Reverse(entry.length));
+                self.scoreboard = entries;
             }
             _ => {}
         }
     }
 
+    /// Queues a turn requested by the player the same way the server does.
+    /// Returns `false` if the server would drop it too.
+    pub fn steer(&mut self, dir: Direction) -> bool {
+        let Some(current_dir) = rules::heading(&self.my_snake.segments) else {
+            return false;
+        };
+        rules::queue_turn(
+            &mut self.pending_turns,
+            current_dir,
+            dir,
+            self.input_queue_depth as usize,
+        )
+    }
+
+    /// Checks the prediction for `tick` against what the server did, drops it
+    /// entirely on mismatch and re-predicts from the authoritative snake.
+    fn reconcile(&mut self, tick: u64, my_move: Option<Direction>, grew: bool) {
+        self.server_tick = tick;
+
+        while let Some(step) = self.predicted_steps.front()
+            && step.tick < tick
+        {
+            self.predicted_steps.pop_front();
+        }
+
+        if let Some(step) = self.predicted_steps.front()
+            && step.tick == tick
+        {
+            if my_move == Some(step.dir) && step.grew == grew {
+                self.predicted_steps.pop_front();
+            } else {
+                // Roll back to the server's version, the turns of later steps get
+                // predicted again. The server already used or dropped this step's turn.
+                debug!(tick, predicted = ?step.dir, confirmed = ?my_move, "Misprediction.");
+                self.mispredictions += 1;
+                self.predicted_steps.pop_front();
+                for step in self.predicted_steps.drain(..).rev() {
+                    if let Some(turn) = step.turn {
+                        self.pending_turns.push_front(turn);
+                    }
+                }
+            }
+        }
+
+        self.predict();
+    }
+
+    /// Rebuilds `my_snake` from the confirmed snake and extends the prediction
+    /// so that it runs about one round trip ahead of the server.
+    fn predict(&mut self) {
+        let mut segments = self.confirmed_snake.clone();
+        for step in &self.predicted_steps {
+            let new_head = rules::next_head(*segments.front().unwrap(), step.dir);
+            rules::advance(&mut segments, new_head, step.grew);
+        }
+
+        let lead = self.prediction_lead() as usize;
+        let is_ghost = self.ghosts.contains(&self.my_id);
+
+        while self.predicted_steps.len() < lead {
+            let (Some(&head), Some(current_dir)) = (segments.front(), rules::heading(&segments))
+            else {
+                break;
+            };
+            let turn = self.pending_turns.front().copied();
+            let dir = rules::next_direction(&mut self.pending_turns, current_dir);
+
+            let new_head = rules::next_head(head, dir);
+            // Deaths are left to the server.
+            if !rules::is_in_bounds(&new_head) {
+                break;
+            }
+
+            let grew = rules::eats(new_head, self.food, is_ghost);
+            rules::advance(&mut segments, new_head, grew);
+            self.predicted_steps.push_back(PredictedStep {
+                tick: self.server_tick + self.predicted_steps.len() as u64 + 1,
+                dir,
+                grew,
+                turn,
+            });
+        }
+
+        // Without prediction the turns are only applied by the server.
+        if lead == 0 {
+            self.pending_turns.clear();
+        }
+
+        self.my_snake.segments = segments;
+    }
+
+    /// Time between ticks, as measured by the server when known.
+    pub fn tick_ms(&self) -> f32 {
+        self.measured_tick_ms
+            .unwrap_or(self.tick_duration_ms as f32)
+            .max(1.0)
+    }
+
+    fn prediction_lead(&self) -> u32 {
+        self.my_rtt_ms().map_or(0, |rtt_ms| {
+            ((rtt_ms as f32 / self.tick_ms()).ceil() as u32).min(MAX_PREDICTED_TICKS)
+        })
+    }
+
+    pub fn my_rtt_ms(&self) -> Option<u32> {
+        self.scoreboard
+            .iter()
+            .find(|entry| entry.id == self.my_id)
+            .and_then(|entry| entry.rtt_ms)
+    }
+
     pub fn snapshot_state(&mut self) {
         self.prev_my_snake = Some(self.my_snake.segments.clone());
         self.prev_other_snakes.clear();
@@ -3,7 +3,10 @@ use crossterm::{
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
-use futures_util::StreamExt;
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
 use ratatui::{
     Terminal,
     backend::{Backend, CrosstermBackend},
@@ -11,19 +14,72 @@ use ratatui::{
     style::{Color, Modifier, Style},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
 };
-use std::{error::Error, io};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use std::{collections::BTreeMap, error::Error, io};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{self, protocol::Message},
+};
+use tracing::{info, warn};
+
+use multisnake_shared::{
+    GameMode, LeaderboardEntry, Leaderboards, LobbyMessage, LobbyRequest, LobbyUpdate, RatingEntry,
+};
+
+type LobbySocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const LEADERBOARD_WIDTH: u16 = 44;
+
+struct Lobby {
+    tx: SplitSink<LobbySocket, Message>,
+    rx: SplitStream<LobbySocket>,
+}
+
+pub enum RoomTarget {
+    Id(u32),
+    InviteCode(String),
+    // A room simulated by the client itself.
+    Offline,
+}
+
+/// The room picked in the selector and the password to join it with.
+pub struct RoomChoice {
+    pub target: RoomTarget,
+    pub password: Option<String>,
+}
+
+impl RoomChoice {
+    pub fn path(&self) -> String {
+        match &self.target {
+            RoomTarget::Id(room_id) => format!("/room/{}", room_id),
+            RoomTarget::InviteCode(code) => format!("/invite/{}", code),
+            RoomTarget::Offline => "offline".to_string(),
+        }
+    }
+}
 
-use multisnake_shared::{LobbyUpdate, N_ROOMS};
+/// Which leaderboard the lobby shows, `l` goes to the next one.
+#[derive(Clone, Copy)]
+enum LeaderboardView {
+    AllTime,
+    Today,
+    Ratings,
+}
 
-pub async fn run_room_selector(server_addr: &str) -> Result<Option<u32>, Box<dyn Error>> {
+/// `notice` is shown above the room list, e.g. why the last game ended.
+/// Quick play prefers ranked rooms of players rated like `name`.
+pub async fn run_room_selector(
+    server_addr: &str,
+    notice: Option<String>,
+    name: Option<String>,
+) -> Result<Option<RoomChoice>, Box<dyn Error>> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let result = run_app(&mut terminal, server_addr).await;
+    let result = run_app(&mut terminal, server_addr, notice, name).await;
 
     disable_raw_mode()?;
     execute!(
@@ -36,27 +92,102 @@ pub async fn run_room_selector(server_addr: &str) -> Result<Option<u32>, Box<dyn
     result
 }
 
+async fn connect_lobby(url: &str) -> Option<Lobby> {
+    let (ws_stream, _) = match connect_async(url).await {
+        Ok(connected) => connected,
+        Err(err) => {
+            warn!(url, "Could not connect to the lobby: {}", err);
+            return None;
+        }
+    };
+    let (tx, rx) = ws_stream.split();
+    Some(Lobby { tx, rx })
+}
+
+/// Never resolves while disconnected, so it can sit in a `select!`.
+async fn next_lobby_message(
+    lobby: &mut Option<Lobby>,
+) -> Option<Result<Message, tungstenite::Error>> {
+    match lobby {
+        Some(lobby) => lobby.rx.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Asks the server for a room, the answer comes back as a `LobbyMessage`.
+async fn request_quick_play(
+    lobby: &mut Lobby,
+    mode: Option<GameMode>,
+    name: Option<String>,
+) -> bool {
+    let Ok(json) = serde_json::to_string(&LobbyRequest::QuickPlay { mode, name }) else {
+        return false;
+    };
+    lobby.tx.send(Message::Text(json.into())).await.is_ok()
+}
+
+/// `3. alice  len 42  5 kills`
+fn leaderboard_line(place: usize, entry: &LeaderboardEntry) -> String {
+    format!(
+        "{:>2}. {:<16} len {:>3}  {} kills",
+        place, entry.name, entry.stats.best_length, entry.stats.total_kills
+    )
+}
+
+fn leaderboard_items(entries: &[LeaderboardEntry]) -> Vec<ListItem<'static>> {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| ListItem::new(leaderboard_line(i + 1, entry)))
+        .collect()
+}
+
+/// `3. alice  1612  40 rounds`
+fn rating_line(place: usize, entry: &RatingEntry) -> String {
+    format!(
+        "{:>2}. {:<16} {:>4}  {} rounds",
+        place, entry.name, entry.rating, entry.rounds
+    )
+}
+
+/// What the keyboard is typing into, if anything.
+enum Prompt {
+    InviteCode(String),
+    Password { target: RoomTarget, input: String },
+}
+
 async fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
     server_addr: &str,
-) -> Result<Option<u32>, Box<dyn Error>> {
-    let mut rooms_count = [0; N_ROOMS as usize]; // TODO
+    mut notice: Option<String>,
+    name: Option<String>,
+) -> Result<Option<RoomChoice>, Box<dyn Error>> {
+    // Public rooms as last reported by the server.
+    let mut rooms: BTreeMap<u32, LobbyUpdate> = BTreeMap::new();
     let mut list_state = ListState::default();
     list_state.select(Some(0));
+    let mut prompt: Option<Prompt> = None;
+    // Mode asked for by quick play, `None` takes any.
+    let mut quick_play_mode: Option<GameMode> = None;
+    let mut leaderboards = Leaderboards::default();
+    let mut leaderboard_view = LeaderboardView::AllTime;
 
     let mut event_stream = EventStream::new();
 
     let url = format!("ws://{}/room", server_addr);
 
-    let (ws_stream, _) = connect_async(url).await?;
-    let (_, mut ws_rx) = ws_stream.split();
+    let mut lobby = connect_lobby(&url).await;
 
     loop {
         terminal.draw(|f| {
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(2)
-                .constraints([Constraint::Length(3), Constraint::Min(0)])
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Length(4),
+                    Constraint::Min(0),
+                ])
                 .split(f.area());
 
             let title = Paragraph::new("multisnake")
@@ -68,14 +199,64 @@ async fn run_app<B: Backend>(
                 .block(Block::default().borders(Borders::ALL));
             f.render_widget(title, chunks[0]);
 
-            let items: Vec<ListItem> = rooms_count
-                .iter()
-                .enumerate()
-                .map(|(i, count)| {
-                    let content = format!("Room {}  [{} players]", i + 1, count);
-                    ListItem::new(content).style(Style::default().fg(Color::White))
-                })
-                .collect();
+            let status_text = match &prompt {
+                Some(Prompt::InviteCode(input)) => {
+                    format!("Invite code: {}\nEnter: next, Esc: cancel", input)
+                }
+                Some(Prompt::Password { input, .. }) => format!(
+                    "Password (empty if none): {}\nEnter: join, Esc: cancel",
+                    "*".repeat(input.chars().count())
+                ),
+                None => {
+                    let connection = if lobby.is_some() {
+                        "Enter: join, m: quick play mode, c: join by invite code, l: leaderboard, q: quit"
+                    } else {
+                        "Not connected to the server, r: reconnect, c: join by invite code, Enter on Play offline, q: quit"
+                    };
+                    format!("{}\n{}", notice.as_deref().unwrap_or(""), connection)
+                }
+            };
+            let status = Paragraph::new(status_text)
+                .style(Style::default().fg(Color::Yellow))
+                .block(Block::default().borders(Borders::ALL));
+            f.render_widget(status, chunks[1]);
+
+            let quick_play = format!(
+                "Quick play  [{}]",
+                quick_play_mode.map_or("any mode", GameMode::as_str)
+            );
+            let items: Vec<ListItem> =
+                std::iter::once(ListItem::new(quick_play).style(Style::default().fg(Color::Green)))
+                    .chain(std::iter::once(
+                        ListItem::new("Play offline").style(Style::default().fg(Color::Green)),
+                    ))
+                    .chain(rooms.values().map(|room| {
+                        let lock = if room.locked { "  [locked]" } else { "" };
+                        let account = if room.requires_account { "  [accounts only]" } else { "" };
+                        let ranked = match (room.ranked, room.rating) {
+                            (true, Some(rating)) => format!("  [ranked ~{}]", rating),
+                            (true, None) => "  [ranked]".to_string(),
+                            (false, _) => String::new(),
+                        };
+                        let bots = if room.bots > 0 {
+                            format!(" + {} bots", room.bots)
+                        } else {
+                            String::new()
+                        };
+                        let content = format!(
+                            "Room {}  [{}/{} players{}]  {}{}{}{}",
+                            room.room_id,
+                            room.player_count,
+                            room.max_players,
+                            bots,
+                            room.mode.as_str(),
+                            ranked,
+                            lock,
+                            account
+                        );
+                        ListItem::new(content).style(Style::default().fg(Color::White))
+                    }))
+                    .collect();
 
             let list = List::new(items)
                 .block(Block::default().borders(Borders::ALL).title("Select room"))
@@ -86,50 +267,162 @@ async fn run_app<B: Backend>(
                 )
                 .highlight_symbol(">> ");
 
-            f.render_stateful_widget(list, chunks[1], &mut list_state);
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(LEADERBOARD_WIDTH)])
+                .split(chunks[2]);
+            f.render_stateful_widget(list, columns[0], &mut list_state);
+
+            let (title, lines): (&str, Vec<ListItem>) = match leaderboard_view {
+                LeaderboardView::AllTime => ("All time", leaderboard_items(&leaderboards.all_time)),
+                LeaderboardView::Today => ("Today", leaderboard_items(&leaderboards.daily)),
+                LeaderboardView::Ratings => (
+                    "Ratings",
+                    leaderboards
+                        .ratings
+                        .iter()
+                        .enumerate()
+                        .map(|(i, entry)| ListItem::new(rating_line(i + 1, entry)))
+                        .collect(),
+                ),
+            };
+            let leaderboard =
+                List::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(leaderboard, columns[1]);
         })?;
 
         tokio::select! {
             maybe_event = event_stream.next() => {
-                if let Some(Ok(Event::Key(key))) = maybe_event {
-                    match key.code {
-                        KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
-                        KeyCode::Up => {
-                            let i = list_state.selected().map_or(0, |i| if i == 0 { 2 } else { i - 1 });
-                            list_state.select(Some(i));
+                let Some(Ok(Event::Key(key))) = maybe_event else { continue };
+
+                if let Some(current) = prompt.take() {
+                    prompt = match (current, key.code) {
+                        (_, KeyCode::Esc) => None,
+                        (Prompt::InviteCode(mut input), KeyCode::Char(c)) => {
+                            if c.is_ascii_alphanumeric() {
+                                input.push(c.to_ascii_uppercase());
+                            }
+                            Some(Prompt::InviteCode(input))
+                        }
+                        (Prompt::InviteCode(mut input), KeyCode::Backspace) => {
+                            input.pop();
+                            Some(Prompt::InviteCode(input))
                         }
-                        KeyCode::Down => {
-                            let i = list_state.selected().map_or(0, |i| if i >= 2 { 0 } else { i + 1 });
-                            list_state.select(Some(i));
+                        (Prompt::InviteCode(input), KeyCode::Enter) if input.is_empty() => None,
+                        // The lobby does not know private rooms, so the password is always asked.
+                        (Prompt::InviteCode(input), KeyCode::Enter) => Some(Prompt::Password {
+                            target: RoomTarget::InviteCode(input),
+                            input: String::new(),
+                        }),
+                        (Prompt::Password { target, mut input }, KeyCode::Char(c)) => {
+                            input.push(c);
+                            Some(Prompt::Password { target, input })
                         }
-                        KeyCode::Enter => {
-                            if let Some(i) = list_state.selected() {
-                                return Ok(Some((i + 1) as u32));
+                        (Prompt::Password { target, mut input }, KeyCode::Backspace) => {
+                            input.pop();
+                            Some(Prompt::Password { target, input })
+                        }
+                        (Prompt::Password { target, input }, KeyCode::Enter) => {
+                            let password = (!input.is_empty()).then_some(input);
+                            return Ok(Some(RoomChoice { target, password }));
+                        }
+                        (current, _) => Some(current),
+                    };
+                    continue;
+                }
+
+                // Quick play and offline play come first, then the rooms.
+                let item_count = rooms.len() + 2;
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                    KeyCode::Up => {
+                        let i = list_state.selected().map_or(0, |i| if i == 0 { item_count - 1 } else { i - 1 });
+                        list_state.select(Some(i));
+                    }
+                    KeyCode::Down => {
+                        let i = list_state.selected().map_or(0, |i| if i + 1 >= item_count { 0 } else { i + 1 });
+                        list_state.select(Some(i));
+                    }
+                    KeyCode::Char('r') if lobby.is_none() => {
+                        lobby = connect_lobby(&url).await;
+                    }
+                    KeyCode::Char('c') => prompt = Some(Prompt::InviteCode(String::new())),
+                    KeyCode::Char('l') => {
+                        leaderboard_view = match leaderboard_view {
+                            LeaderboardView::AllTime => LeaderboardView::Today,
+                            LeaderboardView::Today => LeaderboardView::Ratings,
+                            LeaderboardView::Ratings => LeaderboardView::AllTime,
+                        };
+                    }
+                    KeyCode::Char('m') => {
+                        quick_play_mode = match quick_play_mode {
+                            None => Some(GameMode::Classic),
+                            Some(GameMode::Classic) => Some(GameMode::Fast),
+                            Some(GameMode::Fast) => None,
+                        };
+                    }
+                    KeyCode::Enter if list_state.selected() == Some(0) => {
+                        if let Some(connected) = lobby.as_mut() {
+                            notice = Some(if request_quick_play(connected, quick_play_mode, name.clone()).await {
+                                "Looking for a room...".to_string()
+                            } else {
+                                "Could not ask the server for a room.".to_string()
+                            });
+                        }
+                    }
+                    KeyCode::Enter if list_state.selected() == Some(1) => {
+                        return Ok(Some(RoomChoice { target: RoomTarget::Offline, password: None }));
+                    }
+                    KeyCode::Enter if lobby.is_some() => {
+                        let selected = list_state
+                            .selected()
+                            .and_then(|i| i.checked_sub(2))
+                            .and_then(|i| rooms.values().nth(i));
+                        if let Some(room) = selected {
+                            let target = RoomTarget::Id(room.room_id);
+                            if room.locked {
+                                prompt = Some(Prompt::Password { target, input: String::new() });
+                            } else {
+                                return Ok(Some(RoomChoice { target, password: None }));
                             }
                         }
-                        _ => {}
                     }
+                    _ => {}
                 }
             }
-            maybe_message = ws_rx.next() => {
+            maybe_message = next_lobby_message(&mut lobby) => {
                 match maybe_message {
                     Some(Ok(Message::Text(text))) => {
-                        if let Ok(update) = serde_json::from_str::<LobbyUpdate>(&text) {
-                            let idx = (update.room_id - 1) as usize;
-                            if idx < rooms_count.len() {
-                                rooms_count[idx] = update.player_count;
+                        match serde_json::from_str::<LobbyMessage>(&text) {
+                            Ok(LobbyMessage::RoomUpdate(update)) => {
+                                rooms.insert(update.room_id, update);
+                            }
+                            Ok(LobbyMessage::ServerShutdown { reason, seconds }) => {
+                                notice = Some(format!("{} Closing in {} s.", reason, seconds));
                             }
+                            Ok(LobbyMessage::ServerNotice { message }) => notice = Some(message),
+                            Ok(LobbyMessage::QuickPlayMatch { room_id }) => {
+                                info!(room_id, "Quick play picked a room.");
+                                return Ok(Some(RoomChoice {
+                                    target: RoomTarget::Id(room_id),
+                                    password: None,
+                                }));
+                            }
+                            Ok(LobbyMessage::QuickPlayFailed { reason }) => notice = Some(reason),
+                            Ok(LobbyMessage::Leaderboard(update)) => leaderboards = update,
+                            Ok(LobbyMessage::RoomClosed { room_id }) => {
+                                rooms.remove(&room_id);
+                            }
+                            Err(err) => warn!("Could not decode a lobby message: {}", err),
                         }
                     }
-                    None => {
-                        // TODO: dont stop running TUI on disconnect
-                        break;
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        info!("Lost the connection to the lobby.");
+                        lobby = None;
                     }
                     _ => {}
                 }
             }
         }
     }
-
-    Ok(None)
 }
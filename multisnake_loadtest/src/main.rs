@@ -0,0 +1,137 @@
+//! Headless load generator: many simulated players on their own connections,
+//! reporting how the server holds up. Needs neither a window nor a terminal.
+
+mod player;
+mod stats;
+
+use clap::Parser;
+use player::PlayerConfig;
+use stats::Stats;
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+use multisnake_shared::Direction;
+use multisnake_shared::controller::BotLevel;
+
+#[derive(Parser)]
+struct Args {
+    #[arg(default_value = "127.0.0.1:4040")]
+    server_addr: String,
+    /// Simulated players, each on its own connection.
+    #[arg(long, default_value = "10")]
+    players: usize,
+    /// Room to join, players are spread over the given rooms in turn. Can be repeated.
+    #[arg(long = "room", default_value = "1")]
+    rooms: Vec<u32>,
+    /// Password of the rooms, if they need one.
+    #[arg(long)]
+    password: Option<String>,
+    /// How long the players keep playing, joining again after each death.
+    #[arg(long, default_value = "30")]
+    duration_secs: u64,
+    /// Delay between two players joining.
+    #[arg(long, default_value = "50")]
+    ramp_up_ms: u64,
+    /// Turns played in a loop, e.g. `up,left,down,right`. Turns are random otherwise.
+    #[arg(long, value_delimiter = ',', value_parser = parse_direction)]
+    script: Vec<Direction>,
+    /// Time between two turns of a player.
+    #[arg(long, default_value = "250")]
+    input_interval_ms: u64,
+    /// Let a built-in bot steer each player on every tick, instead of turning every input interval.
+    #[arg(long, value_enum)]
+    controller: Option<BotLevel>,
+    /// Players are named `<prefix>-<n>`.
+    #[arg(long, default_value = "loadtest")]
+    name_prefix: String,
+    /// Log filter for stderr, e.g. `info` (`RUST_LOG` overrides it).
+    #[arg(long, default_value = "warn")]
+    log_level: String,
+}
+
+fn parse_direction(value: &str) -> Result<Direction, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "up" | "u" => Ok(Direction::Up),
+        "down" | "d" => Ok(Direction::Down),
+        "left" | "l" => Ok(Direction::Left),
+        "right" | "r" => Ok(Direction::Right),
+        _ => Err(format!("unknown direction {:?}", value)),
+    }
+}
+
+fn room_url(args: &Args, room_id: u32, name: &str) -> String {
+    let url = format!("ws://{}/room/{}", args.server_addr, room_id);
+    let params: Vec<(&str, &str)> = [("name", Some(name)), ("password", args.password.as_deref())]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect();
+    match serde_urlencoded::to_string(params) {
+        Ok(query) if !query.is_empty() => format!("{}?{}", url, query),
+        _ => url,
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&args.log_level))
+        .unwrap_or_else(|_| EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            error!("Could not start the runtime: {}", err);
+            return;
+        }
+    };
+
+    let started = Instant::now();
+    let config = Arc::new(PlayerConfig {
+        script: args.script.clone(),
+        input_interval: Duration::from_millis(args.input_interval_ms.max(1)),
+        controller: args.controller,
+        deadline: started + Duration::from_secs(args.duration_secs),
+    });
+
+    // Players block on their messages, so each gets a thread while the
+    // connections share the runtime.
+    let mut players = Vec::new();
+    for i in 0..args.players {
+        if Instant::now() >= config.deadline {
+            break;
+        }
+        let room_id = args.rooms[i % args.rooms.len()];
+        let name = format!("{}-{}", args.name_prefix, i + 1);
+        let url = room_url(&args, room_id, &name);
+        let handle = runtime.handle().clone();
+        let config = config.clone();
+        let spawned = thread::Builder::new()
+            .name(name)
+            .spawn(move || player::play(&handle, &config, &url));
+        match spawned {
+            Ok(player) => players.push(player),
+            Err(err) => error!("Could not start player {}: {}", i + 1, err),
+        }
+        thread::sleep(Duration::from_millis(args.ramp_up_ms));
+    }
+    info!(players = players.len(), "All players started.");
+
+    let started_players = players.len();
+    let mut stats = Stats::default();
+    for player in players {
+        match player.join() {
+            Ok(player_stats) => stats.merge(player_stats),
+            Err(_) => error!("A player thread panicked."),
+        }
+    }
+    print!("{}", stats.report(started_players, started.elapsed()));
+}
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::stats::Stats;
+use multisnake_client::room_connection;
+use multisnake_client::room_state::RoomState;
+use multisnake_shared::controller::{BotLevel, RoomView, SnakeController};
+use multisnake_shared::rules::{self, idx, is_in_bounds};
+use multisnake_shared::{Direction, GRID_H, GRID_W, Pos, ScoreEntry, SnakeMessage};
+
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+
+pub struct PlayerConfig {
+    // Turns played in a loop, random turns when empty.
+    pub script: Vec<Direction>,
+    pub input_interval: Duration,
+    // Steers on every tick instead of following the script.
+    pub controller: Option<BotLevel>,
+    pub deadline: Instant,
+}
+
+/// Plays on `url` until the deadline, joining again after each death.
+/// Gives up if the server refuses it.
+pub fn play(handle: &Handle, config: &PlayerConfig, url: &str) -> Stats {
+    let mut stats = Stats::default();
+    let mut turns = 0;
+    while Instant::now() < config.deadline {
+        if !play_once(handle, config, url, &mut stats, &mut turns) {
+            break;
+        }
+    }
+    stats
+}
+
+/// One life of a snake. Returns `false` if the player could not join.
+fn play_once(
+    handle: &Handle,
+    config: &PlayerConfig,
+    url: &str,
+    stats: &mut Stats,
+    turns: &mut usize,
+) -> bool {
+    let (from_client_tx, from_client_rx) = mpsc::unbounded_channel();
+    let (from_server_tx, from_server_rx) = std::sync::mpsc::channel();
+    let started = Instant::now();
+    let connection = handle.spawn(room_connection::run(
+        url.to_string(),
+        from_client_rx,
+        from_server_tx,
+    ));
+
+    let joined = from_server_rx.recv_timeout(JOIN_TIMEOUT);
+    let join_bytes = joined.as_ref().map_or(0, encoded_len);
+    let Ok(SnakeMessage::OnJoin {
+        my_id,
+        snakes,
+        tick_duration_ms,
+        input_queue_depth,
+        food,
+        tick,
+        ..
+    }) = joined
+    else {
+        stats.failed_joins += 1;
+        let reason = match joined {
+            Err(RecvTimeoutError::Timeout) => {
+                connection.abort();
+                Some("Timed out.".to_string())
+            }
+            _ => handle.block_on(connection).ok().flatten(),
+        };
+        warn!(?reason, "Could not join.");
+        return false;
+    };
+    stats.joins += 1;
+    stats
+        .join_latencies_ms
+        .push(started.elapsed().as_secs_f64() * 1000.0);
+    stats.messages += 1;
+    stats.bytes += join_bytes;
+
+    let mut state = RoomState::new(
+        my_id,
+        snakes,
+        tick_duration_ms,
+        input_queue_depth,
+        food,
+        tick,
+    );
+    let mut last_tick = tick;
+    let mut last_tick_at = Instant::now();
+    let mut next_input = Instant::now() + config.input_interval;
+    let mut controller = config.controller.map(BotLevel::controller);
+
+    loop {
+        let now = Instant::now();
+        if now >= config.deadline {
+            break;
+        }
+        if now >= next_input {
+            next_input += config.input_interval;
+            if controller.is_some() {
+                continue;
+            }
+            let dir = next_turn(config, turns);
+            if state.steer(dir) {
+                let _ = from_client_tx.send(SnakeMessage::MoveIntent {
+                    dir,
+                    tick: state.server_tick,
+                });
+            }
+            continue;
+        }
+
+        let msg = match from_server_rx.recv_timeout(next_input.min(config.deadline) - now) {
+            Ok(msg) => msg,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                debug!("The connection ended.");
+                break;
+            }
+        };
+        stats.messages += 1;
+        stats.bytes += encoded_len(&msg);
+
+        match &msg {
+            SnakeMessage::OnJoin { tick, .. } => {
+                last_tick = *tick;
+                last_tick_at = Instant::now();
+            }
+            SnakeMessage::TickUpdate { tick, .. } if *tick > last_tick => {
+                let expected_ms = (tick - last_tick) as f64 * state.tick_duration_ms as f64;
+                let actual_ms = last_tick_at.elapsed().as_secs_f64() * 1000.0;
+                stats.tick_jitter_ms.push((actual_ms - expected_ms).abs());
+                stats.tick_gaps += tick - last_tick - 1;
+                last_tick = *tick;
+                last_tick_at = Instant::now();
+            }
+            SnakeMessage::Scoreboard { entries } => {
+                stats.scoreboard_mismatches += scoreboard_mismatches(&state, entries);
+            }
+            _ => {}
+        }
+
+        let ticked = matches!(msg, SnakeMessage::TickUpdate { .. });
+        state.process_message(msg);
+        if !state.alive {
+            stats.deaths += 1;
+            break;
+        }
+
+        if ticked && let Some(controller) = controller.as_mut() {
+            let dir = choose(controller.as_mut(), &state);
+            if state.steer(dir) {
+                let _ = from_client_tx.send(SnakeMessage::MoveIntent {
+                    dir,
+                    tick: state.server_tick,
+                });
+            }
+        }
+    }
+
+    stats.mispredictions += state.mispredictions;
+    // Hanging up closes the socket and ends the connection task.
+    drop(from_client_tx);
+    let _ = handle.block_on(connection);
+    true
+}
+
+fn next_turn(config: &PlayerConfig, turns: &mut usize) -> Direction {
+    let dir = if config.script.is_empty() {
+        DIRECTIONS[rand::random_range(0..DIRECTIONS.len())]
+    } else {
+        config.script[*turns % config.script.len()]
+    };
+    *turns += 1;
+    dir
+}
+
+/// Asks `controller` for a turn, showing it the room as last confirmed by the server.
+fn choose(controller: &mut dyn SnakeController, state: &RoomState) -> Direction {
+    let mut snakes: Vec<(Uuid, &VecDeque<Pos>)> = vec![(state.my_id, &state.confirmed_snake)];
+    snakes.extend(
+        state
+            .other_snakes
+            .iter()
+            .map(|(id, snake)| (*id, &snake.segments)),
+    );
+    let mut grid = vec![0; (GRID_W * GRID_H) as usize];
+    for (_, snake) in &snakes {
+        for p in snake.iter().filter(|p| is_in_bounds(p)) {
+            grid[idx(p)] += 1;
+        }
+    }
+
+    let view = RoomView {
+        my_id: state.my_id,
+        snake: &state.confirmed_snake,
+        dir: rules::heading(&state.confirmed_snake).unwrap_or(Direction::Up),
+        ghost: state.ghosts.contains(&state.my_id),
+        food: state.food,
+        snakes: &snakes,
+        grid: &grid,
+    };
+    controller.choose(&view)
+}
+
+/// Snakes whose length on the server differs from the one tracked locally.
+fn scoreboard_mismatches(state: &RoomState, entries: &[ScoreEntry]) -> u64 {
+    entries
+        .iter()
+        .filter(|entry| {
+            let tracked = if entry.id == state.my_id {
+                Some(state.confirmed_snake.len())
+            } else {
+                state.other_snakes.get(&entry.id).map(|s| s.segments.len())
+            };
+            if tracked != Some(entry.length) {
+                warn!(id = %entry.id, length = entry.length, ?tracked, "Scoreboard mismatch.");
+                return true;
+            }
+            false
+        })
+        .count() as u64
+}
+
+fn encoded_len(msg: &SnakeMessage) -> u64 {
+    serde_json::to_string(msg).map_or(0, |json| json.len() as u64)
+}
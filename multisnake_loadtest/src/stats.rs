@@ -0,0 +1,90 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+/// What the simulated players measured, merged into one report at the end of the run.
+#[derive(Default)]
+pub struct Stats {
+    pub joins: u64,
+    pub failed_joins: u64,
+    pub deaths: u64,
+    // From starting the connection to receiving `OnJoin`.
+    pub join_latencies_ms: Vec<f64>,
+    // How far each tick arrived from the time the tick duration announced.
+    pub tick_jitter_ms: Vec<f64>,
+    pub messages: u64,
+    // Size of the received messages once encoded again, which is what the server sent.
+    pub bytes: u64,
+    // Ticks that never arrived.
+    pub tick_gaps: u64,
+    // Scoreboard lengths that differ from the snakes tracked by `RoomState`.
+    pub scoreboard_mismatches: u64,
+    pub mispredictions: u64,
+}
+
+impl Stats {
+    pub fn merge(&mut self, other: Stats) {
+        self.joins += other.joins;
+        self.failed_joins += other.failed_joins;
+        self.deaths += other.deaths;
+        self.join_latencies_ms.extend(other.join_latencies_ms);
+        self.tick_jitter_ms.extend(other.tick_jitter_ms);
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.tick_gaps += other.tick_gaps;
+        self.scoreboard_mismatches += other.scoreboard_mismatches;
+        self.mispredictions += other.mispredictions;
+    }
+
+    pub fn report(&mut self, players: usize, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "players: {} over {:.1} s (joins {}, failed joins {}, deaths {})",
+            players, secs, self.joins, self.failed_joins, self.deaths
+        );
+        let _ = writeln!(
+            report,
+            "join latency ms: {}",
+            summary(&mut self.join_latencies_ms)
+        );
+        let _ = writeln!(
+            report,
+            "tick jitter ms: {}",
+            summary(&mut self.tick_jitter_ms)
+        );
+        let _ = writeln!(
+            report,
+            "received: {} messages ({:.0}/s), {} bytes ({:.1} KiB/s)",
+            self.messages,
+            self.messages as f64 / secs,
+            self.bytes,
+            self.bytes as f64 / 1024.0 / secs
+        );
+        let _ = writeln!(
+            report,
+            "desyncs: {} (tick gaps {}, scoreboard mismatches {}), mispredictions {}",
+            self.tick_gaps + self.scoreboard_mismatches,
+            self.tick_gaps,
+            self.scoreboard_mismatches,
+            self.mispredictions
+        );
+        report
+    }
+}
+
+fn summary(samples: &mut [f64]) -> String {
+    if samples.is_empty() {
+        return "-".to_string();
+    }
+    samples.sort_by(f64::total_cmp);
+    let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+    let avg = samples.iter().sum::<f64>() / samples.len() as f64;
+    format!(
+        "avg {:.2}, p50 {:.2}, p99 {:.2}, max {:.2}",
+        avg,
+        percentile(0.5),
+        percentile(0.99),
+        samples[samples.len() - 1]
+    )
+}
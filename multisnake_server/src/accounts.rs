@@ -0,0 +1,314 @@
+use axum::{
+    Json,
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::socket_handlers::sanitize_name;
+use multisnake_shared::{AccountRequest, LoginResponse};
+
+const PBKDF2_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+// Logging in once more signs the account out everywhere else.
+const MAX_SESSIONS_PER_ACCOUNT: usize = 8;
+// Passwords hashed at the same time, the others are refused.
+const MAX_CONCURRENT_HASHES: usize = 4;
+// Registrations and logins allowed per address within `ATTEMPT_WINDOW`.
+const MAX_ATTEMPTS_PER_ADDRESS: u32 = 10;
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Account {
+    // As registered, accounts are looked up case-insensitively.
+    name: String,
+    // Hex encoded.
+    salt: String,
+    // PBKDF2-HMAC-SHA256 of the password, hex encoded.
+    hash: String,
+    rounds: u32,
+    created_unix_secs: u64,
+}
+
+impl Account {
+    fn verify(&self, password: &str) -> bool {
+        let (Ok(salt), Ok(expected)) = (hex::decode(&self.salt), hex::decode(&self.hash)) else {
+            return false;
+        };
+        let hash = hash_password(password, &salt, self.rounds);
+        same_bytes(&hash, &expected)
+    }
+}
+
+/// Why registering or logging in failed, with the status to answer.
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidName,
+    InvalidPassword,
+    NameTaken,
+    WrongCredentials,
+    // Over the limit of attempts, or every hashing slot is taken.
+    TooManyAttempts,
+    Storage(io::Error),
+}
+
+impl IntoResponse for AccountError {
+    fn into_response(self) -> Response {
+        match self {
+            AccountError::InvalidName => (StatusCode::BAD_REQUEST, "Invalid name.").into_response(),
+            AccountError::InvalidPassword => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Passwords need {} to {} characters.",
+                    MIN_PASSWORD_LEN, MAX_PASSWORD_LEN
+                ),
+            )
+                .into_response(),
+            AccountError::NameTaken => {
+                (StatusCode::CONFLICT, "The name is already registered.").into_response()
+            }
+            AccountError::WrongCredentials => {
+                (StatusCode::UNAUTHORIZED, "Wrong name or password.").into_response()
+            }
+            AccountError::TooManyAttempts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many attempts, try again later.",
+            )
+                .into_response(),
+            AccountError::Storage(err) => {
+                error!("Could not save the accounts: {}", err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+/// Registered players, kept in a JSON file, and the tokens of those logged in.
+/// Tokens only live in memory, a restart logs everyone out.
+pub struct Accounts {
+    path: PathBuf,
+    // By lowercase name.
+    accounts: Mutex<HashMap<String, Account>>,
+    // Lowercase name of each login token.
+    sessions: Mutex<HashMap<Uuid, String>>,
+    // Start of the current window and attempts since, by address.
+    attempts: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+    hashing: Arc<Semaphore>,
+}
+
+impl Accounts {
+    /// Reads the accounts saved at `path`, none if the file does not exist yet.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let accounts = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            accounts: Mutex::new(accounts),
+            sessions: Mutex::new(HashMap::new()),
+            attempts: Mutex::new(HashMap::new()),
+            hashing: Arc::new(Semaphore::new(MAX_CONCURRENT_HASHES)),
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        self.accounts().len()
+    }
+
+    /// Whether someone registered `name`, in any case.
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts().contains_key(&name.to_lowercase())
+    }
+
+    /// The registered name of whoever logged in with `token`.
+    pub fn authenticate(&self, token: Uuid) -> Option<String> {
+        let key = self.sessions().get(&token)?.clone();
+        self.accounts()
+            .get(&key)
+            .map(|account| account.name.clone())
+    }
+
+    /// Counts an attempt from `ip` and takes a hashing slot, to be held while hashing.
+    /// Hashing is slow on purpose, so unlimited attempts would tie up the blocking threads.
+    fn admit(&self, ip: IpAddr) -> Result<OwnedSemaphorePermit, AccountError> {
+        {
+            let mut attempts = self.attempts.lock().unwrap_or_else(|err| err.into_inner());
+            let now = Instant::now();
+            attempts.retain(|_, (since, _)| now.duration_since(*since) < ATTEMPT_WINDOW);
+            let (_, count) = attempts.entry(ip).or_insert((now, 0));
+            *count += 1;
+            if *count > MAX_ATTEMPTS_PER_ADDRESS {
+                return Err(AccountError::TooManyAttempts);
+            }
+        }
+        self.hashing
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| AccountError::TooManyAttempts)
+    }
+
+    /// Slow on purpose, run it off the async threads.
+    fn register(&self, name: &str, password: &str) -> Result<String, AccountError> {
+        let name = sanitize_name(name).ok_or(AccountError::InvalidName)?;
+        if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&password.chars().count()) {
+            return Err(AccountError::InvalidPassword);
+        }
+        let key = name.to_lowercase();
+        if self.accounts().contains_key(&key) {
+            return Err(AccountError::NameTaken);
+        }
+
+        let salt: [u8; SALT_LEN] = rand::random();
+        let account = Account {
+            name: name.clone(),
+            salt: hex::encode(salt),
+            hash: hex::encode(hash_password(password, &salt, PBKDF2_ROUNDS)),
+            rounds: PBKDF2_ROUNDS,
+            created_unix_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        };
+
+        let mut accounts = self.accounts();
+        // Someone may have taken it while hashing.
+        if accounts.contains_key(&key) {
+            return Err(AccountError::NameTaken);
+        }
+        accounts.insert(key.clone(), account);
+        if let Err(err) = self.save(&accounts) {
+            accounts.remove(&key);
+            return Err(AccountError::Storage(err));
+        }
+        Ok(name)
+    }
+
+    /// Slow on purpose, run it off the async threads.
+    fn login(&self, name: &str, password: &str) -> Result<LoginResponse, AccountError> {
+        let key = name.trim().to_lowercase();
+        let Some(account) = self.accounts().get(&key).cloned() else {
+            // Hashes all the same, so that timing does not tell which names are registered.
+            std::hint::black_box(hash_password(password, &[0; SALT_LEN], PBKDF2_ROUNDS));
+            return Err(AccountError::WrongCredentials);
+        };
+        if !account.verify(password) {
+            return Err(AccountError::WrongCredentials);
+        }
+
+        let token = Uuid::new_v4();
+        let mut sessions = self.sessions();
+        if sessions.values().filter(|name| **name == key).count() >= MAX_SESSIONS_PER_ACCOUNT {
+            sessions.retain(|_, name| *name != key);
+        }
+        sessions.insert(token, key);
+        Ok(LoginResponse {
+            name: account.name,
+            token,
+        })
+    }
+
+    /// Replaces the file at once, a crash while saving keeps the previous one.
+    fn save(&self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        let json = serde_json::to_vec(accounts)?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, json).and_then(|()| fs::rename(&tmp, &self.path))
+    }
+
+    fn accounts(&self) -> std::sync::MutexGuard<'_, HashMap<String, Account>> {
+        self.accounts.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn sessions(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, String>> {
+        self.sessions.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+fn hash_password(password: &str, salt: &[u8], rounds: u32) -> [u8; HASH_LEN] {
+    let mut hash = [0; HASH_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut hash);
+    hash
+}
+
+/// Compares every byte, so that timing does not tell how much of a secret matched.
+pub fn same_bytes(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub async fn register_handler(
+    State(accounts): State<Arc<Accounts>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<AccountRequest>,
+) -> Response {
+    let permit = match accounts.admit(addr.ip()) {
+        Ok(permit) => permit,
+        Err(err) => {
+            warn!(ip = %addr.ip(), "Refused a registration, too many attempts.");
+            return err.into_response();
+        }
+    };
+    let registered = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        accounts.register(&request.name, &request.password)
+    })
+    .await;
+    match registered {
+        Ok(Ok(name)) => {
+            info!(name, "Account registered.");
+            StatusCode::CREATED.into_response()
+        }
+        Ok(Err(err)) => err.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub async fn login_handler(
+    State(accounts): State<Arc<Accounts>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<AccountRequest>,
+) -> Response {
+    let permit = match accounts.admit(addr.ip()) {
+        Ok(permit) => permit,
+        Err(err) => {
+            warn!(ip = %addr.ip(), "Refused a login, too many attempts.");
+            return err.into_response();
+        }
+    };
+    let name = request.name.clone();
+    let logged_in = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        accounts.login(&request.name, &request.password)
+    })
+    .await;
+    match logged_in {
+        Ok(Ok(response)) => {
+            info!(name = response.name, "Logged in.");
+            Json(response).into_response()
+        }
+        Ok(Err(err)) => {
+            info!(name, "Refused a login.");
+            err.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,285 @@
+use axum::{
+    Json,
+    extract::{Path, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::accounts;
+use crate::room_actor::{RoomCommand, RoomHandle};
+use crate::room_manager::PlayerFilter;
+use crate::rooms::Rooms;
+use multisnake_shared::LobbyMessage;
+
+const KICK_REASON: &str = "You were kicked by an admin.";
+const BAN_REASON: &str = "You were banned by an admin.";
+const RESET_REASON: &str = "The room was reset by an admin.";
+
+pub struct AdminContext {
+    pub token: String,
+    pub rooms: Arc<Rooms>,
+    pub bans: Arc<BanList>,
+    pub lobby_tx: broadcast::Sender<LobbyMessage>,
+}
+
+struct Ban {
+    ip: Option<IpAddr>,
+    name: Option<String>,
+    reason: String,
+    until: Instant,
+}
+
+#[derive(Serialize)]
+pub struct BanStatus {
+    pub ip: Option<IpAddr>,
+    pub name: Option<String>,
+    pub reason: String,
+    pub remaining_secs: u64,
+}
+
+/// Addresses and names that may not join until their ban expires.
+#[derive(Default)]
+pub struct BanList {
+    bans: Mutex<Vec<Ban>>,
+}
+
+impl BanList {
+    /// Returns the reason of the first ban matching `ip` or `name`. Names
+    /// match in any case, like registered names.
+    pub fn check(&self, ip: Option<IpAddr>, name: Option<&str>) -> Option<String> {
+        let mut bans = self.bans.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        bans.retain(|ban| ban.until > now);
+
+        let name = name.map(str::to_lowercase);
+        bans.iter()
+            .find(|ban| {
+                (ban.ip.is_some() && ban.ip == ip) || (ban.name.is_some() && ban.name == name)
+            })
+            .map(|ban| ban.reason.clone())
+    }
+
+    fn add(&self, mut ban: Ban) {
+        ban.name = ban.name.map(|name| name.to_lowercase());
+        let mut bans = self.bans.lock().unwrap_or_else(|err| err.into_inner());
+        bans.push(ban);
+    }
+
+    fn list(&self) -> Vec<BanStatus> {
+        let mut bans = self.bans.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+        bans.retain(|ban| ban.until > now);
+
+        bans.iter()
+            .map(|ban| BanStatus {
+                ip: ban.ip,
+                name: ban.name.clone(),
+                reason: ban.reason.clone(),
+                remaining_secs: ban.until.duration_since(now).as_secs(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct KickRequest {
+    /// Only look in this room, all rooms otherwise.
+    pub room_id: Option<u32>,
+    #[serde(flatten)]
+    pub filter: PlayerFilter,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BanRequest {
+    pub ip: Option<IpAddr>,
+    pub name: Option<String>,
+    pub duration_secs: u64,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NoticeRequest {
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct Kicked {
+    pub kicked: Vec<Uuid>,
+}
+
+/// Accepts `Authorization: Bearer <token>` with the configured token.
+fn authorize(headers: &HeaderMap, ctx: &AdminContext) -> Result<(), StatusCode> {
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if accounts::same_bytes(given.as_bytes(), ctx.token.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Layered on the `/admin` routes, so that the token is checked before a
+/// route looks at the body and tells a stranger what it expects.
+pub async fn require_token(
+    State(ctx): State<Arc<AdminContext>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match authorize(request.headers(), &ctx) {
+        Ok(()) => next.run(request).await,
+        Err(code) => code.into_response(),
+    }
+}
+
+fn find_room(ctx: &AdminContext, room_id: u32) -> Result<RoomHandle, StatusCode> {
+    ctx.rooms
+        .get(room_id)
+        .map(|room| room.room.clone())
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn kick_everywhere(
+    ctx: &AdminContext,
+    room_id: Option<u32>,
+    filter: PlayerFilter,
+    reason: String,
+) -> Vec<Uuid> {
+    let mut kicked = Vec::new();
+    for room in ctx.rooms.handles() {
+        if room_id.is_some_and(|room_id| room.room_id != room_id) {
+            continue;
+        }
+        if let Some(ids) = room.kick(filter.clone(), reason.clone()).await {
+            kicked.extend(ids);
+        }
+    }
+    kicked
+}
+
+pub async fn kick_handler(
+    State(ctx): State<Arc<AdminContext>>,
+    Json(request): Json<KickRequest>,
+) -> Response {
+    let reason = request.reason.unwrap_or_else(|| KICK_REASON.to_string());
+    let kicked = kick_everywhere(&ctx, request.room_id, request.filter, reason).await;
+    Json(Kicked { kicked }).into_response()
+}
+
+/// Bans an address and/or a name and kicks the matching players.
+pub async fn ban_handler(
+    State(ctx): State<Arc<AdminContext>>,
+    Json(request): Json<BanRequest>,
+) -> Response {
+    if request.ip.is_none() && request.name.is_none() {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    // Too long to be represented, e.g. `u64::MAX`.
+    let Some(until) = Instant::now().checked_add(Duration::from_secs(request.duration_secs)) else {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "The ban is too long.").into_response();
+    };
+
+    let reason = request.reason.unwrap_or_else(|| BAN_REASON.to_string());
+    info!(
+        ip = ?request.ip,
+        name = ?request.name,
+        duration_secs = request.duration_secs,
+        "Ban added."
+    );
+    ctx.bans.add(Ban {
+        ip: request.ip,
+        name: request.name.clone(),
+        reason: reason.clone(),
+        until,
+    });
+
+    // A ban on both fields bans either of them, so each is kicked separately.
+    let mut kicked = Vec::new();
+    if let Some(ip) = request.ip {
+        let filter = PlayerFilter {
+            ip: Some(ip),
+            ..Default::default()
+        };
+        kicked.extend(kick_everywhere(&ctx, None, filter, reason.clone()).await);
+    }
+    if let Some(name) = request.name {
+        let filter = PlayerFilter {
+            name: Some(name),
+            ..Default::default()
+        };
+        kicked.extend(kick_everywhere(&ctx, None, filter, reason).await);
+    }
+    Json(Kicked { kicked }).into_response()
+}
+
+pub async fn bans_handler(State(ctx): State<Arc<AdminContext>>) -> Response {
+    Json(ctx.bans.list()).into_response()
+}
+
+pub async fn pause_handler(
+    Path(room_id): Path<u32>,
+    State(ctx): State<Arc<AdminContext>>,
+) -> Response {
+    room_command(&ctx, room_id, RoomCommand::Pause { paused: true }).await
+}
+
+pub async fn resume_handler(
+    Path(room_id): Path<u32>,
+    State(ctx): State<Arc<AdminContext>>,
+) -> Response {
+    room_command(&ctx, room_id, RoomCommand::Pause { paused: false }).await
+}
+
+pub async fn reset_handler(
+    Path(room_id): Path<u32>,
+    State(ctx): State<Arc<AdminContext>>,
+) -> Response {
+    let cmd = RoomCommand::Reset {
+        reason: RESET_REASON.to_string(),
+    };
+    room_command(&ctx, room_id, cmd).await
+}
+
+async fn room_command(ctx: &AdminContext, room_id: u32, cmd: RoomCommand) -> Response {
+    let room = match find_room(ctx, room_id) {
+        Ok(room) => room,
+        Err(code) => return code.into_response(),
+    };
+    if room.send(cmd).await {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// Shows a message in every room and in the room selectors.
+pub async fn notice_handler(
+    State(ctx): State<Arc<AdminContext>>,
+    Json(request): Json<NoticeRequest>,
+) -> Response {
+    info!(message = request.message, "Broadcasting a notice.");
+    for room in ctx.rooms.handles() {
+        room.send(RoomCommand::Notice {
+            message: request.message.clone(),
+        })
+        .await;
+    }
+    // Fails only when no room selector is connected.
+    let _ = ctx.lobby_tx.send(LobbyMessage::ServerNotice {
+        message: request.message,
+    });
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,34 @@
+use std::io::{self, IsTerminal};
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written to stdout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One human readable line per event.
+    Text,
+    /// One JSON object per event, with the fields of the enclosing spans.
+    Json,
+}
+
+/// Installs the global subscriber. `level` is a filter such as `info` or
+/// `info,multisnake_server=debug`; `RUST_LOG` takes precedence over it.
+pub fn init(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(level))
+        .unwrap_or_else(|err| {
+            eprintln!("Invalid log level {:?} ({}), using info.", level, err);
+            EnvFilter::new("info")
+        });
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stdout().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
@@ -1,24 +1,49 @@
+mod accounts;
+mod admin;
+mod logging;
+mod matchmaking;
+mod monitoring;
+mod profiles;
+mod ratings;
+mod recording;
+mod room_actor;
 mod room_manager;
+mod rooms;
+mod shutdown;
 mod socket_handlers;
+mod status;
 
-use axum::{Router, routing::get};
+use accounts::Accounts;
+use admin::{AdminContext, BanList};
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
 use clap::Parser;
-use room_manager::RoomManager;
+use logging::LogFormat;
+use recording::RecordingConfig;
+use room_manager::{DisconnectPolicy, LagPolicy, RoomConfig, TickSchedule};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
 use tokio::sync::broadcast;
-use tokio::sync::mpsc;
-use tokio::time;
+use tokio::time::Instant;
+use tracing::{error, info};
 
-use multisnake_shared::LobbyUpdate;
 use multisnake_shared::N_ROOMS;
+use multisnake_shared::controller::BotLevel;
+use multisnake_shared::{GameMode, LobbyMessage};
 
-use crate::socket_handlers::RoomContext;
+use crate::profiles::Profiles;
+use crate::rooms::Rooms;
 use crate::socket_handlers::TuiContext;
+use crate::status::StatusContext;
 
 const BROADCAST_CAPACITY: usize = 1024;
+const INVITE_CODE_LEN: usize = 6;
 
 #[derive(Parser)]
 struct Args {
@@ -26,77 +51,406 @@ struct Args {
     addr: String,
     #[arg(default_value = "100")]
     tick_duration_ms: u32,
+    /// How rooms catch up on ticks missed while stalled.
+    #[arg(long, value_enum, default_value = "skip")]
+    tick_schedule: TickSchedule,
+    /// How long a disconnected player's snake waits for them to reconnect.
+    #[arg(long, default_value = "10000")]
+    reconnect_grace_ms: u32,
+    /// What a snake does while its player is disconnected.
+    #[arg(long, value_enum, default_value = "continue")]
+    disconnect_policy: DisconnectPolicy,
+    /// How often the server pings each client.
+    #[arg(long, default_value = "2000")]
+    heartbeat_interval_ms: u32,
+    /// Connections that do not answer pings for this long are dropped.
+    #[arg(long, default_value = "6000")]
+    heartbeat_timeout_ms: u32,
+    /// Players that send no input for this long lose their snake (0 disables).
+    #[arg(long, default_value = "60000")]
+    idle_timeout_ms: u32,
+    /// How many turns are buffered per player, one is applied each tick.
+    #[arg(long, default_value = "3")]
+    input_queue_depth: u32,
+    /// Inputs over this many per tick are rejected.
+    #[arg(long, default_value = "4")]
+    max_inputs_per_tick: u32,
+    /// Inputs stamped with a tick older than this are rejected.
+    #[arg(long, default_value = "100")]
+    max_input_age_ticks: u64,
+    /// Kick clients after this many rejected inputs.
+    #[arg(long)]
+    kick_after_rejected_inputs: Option<u32>,
+    /// How many frames are buffered per room for slow clients.
+    #[arg(long, default_value = "64")]
+    frame_buffer: usize,
+    /// What happens to clients that fall further behind than the frame buffer.
+    #[arg(long, value_enum, default_value = "keyframe")]
+    lag_policy: LagPolicy,
+    /// On SIGINT/SIGTERM, how long running games may go on before the server stops.
+    #[arg(long, default_value = "10")]
+    shutdown_timeout_secs: u64,
+    /// Log filter, e.g. `debug` or `info,multisnake_server=trace` (`RUST_LOG` overrides it).
+    #[arg(long, default_value = "info")]
+    log_level: String,
+    /// Write logs as plain text or as one JSON object per line.
+    #[arg(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
+    /// Enables the `/admin` routes, callers must send `Authorization: Bearer <token>`.
+    #[arg(long, env = "MULTISNAKE_ADMIN_TOKEN")]
+    admin_token: Option<String>,
+    /// Require a password to join a room, as `<room id>=<password>`. Can be repeated,
+    /// or given comma separated in the environment to keep them out of `ps`.
+    #[arg(
+        long = "room-password",
+        env = "MULTISNAKE_ROOM_PASSWORDS",
+        value_delimiter = ',',
+        hide_env_values = true,
+        value_parser = parse_room_password
+    )]
+    room_passwords: Vec<(u32, String)>,
+    /// Hide a room from the lobby, players join it by id or with its invite code. Can be repeated.
+    #[arg(long = "private-room")]
+    private_rooms: Vec<u32>,
+    /// Only let players logged in to an account join a room, guests are refused. Can be repeated.
+    #[arg(long = "account-room")]
+    account_rooms: Vec<u32>,
+    /// Play a room in rounds that rate the logged-in players by finishing order. Can be repeated.
+    #[arg(long = "ranked-room")]
+    ranked_rooms: Vec<u32>,
+    /// Longest a round of a ranked room lasts, the survivors are then ranked by length.
+    #[arg(long, default_value = "300")]
+    round_secs: u32,
+    /// Seed a room's food and spawns to reproduce a game, as `<room id>=<seed>`. Can be repeated.
+    /// Rooms without one get a random seed, which is logged when they start.
+    #[arg(long = "room-seed", value_parser = parse_room_seed)]
+    room_seeds: Vec<(u32, u64)>,
+    /// Play a room with other rules, as `<room id>=classic|fast`. Can be repeated.
+    #[arg(long = "room-mode", value_parser = parse_room_mode)]
+    room_modes: Vec<(u32, GameMode)>,
+    /// Joins are refused once a room holds this many players (bots not counted).
+    #[arg(long, default_value = "32")]
+    max_players: usize,
+    /// Quick play opens rooms when every room is full, up to this many rooms in total.
+    #[arg(long, default_value = "16")]
+    max_rooms: usize,
+    /// Fill every room with bots until players and bots make this many snakes.
+    #[arg(long, default_value = "0")]
+    bots: usize,
+    /// How well the bots play.
+    #[arg(long, value_enum, default_value = "lookahead")]
+    bot_level: BotLevel,
+    /// Record every room's games to compressed replay files in this directory,
+    /// played back with the client's `replay` command.
+    #[arg(long)]
+    record_dir: Option<PathBuf>,
+    /// A new replay file is started once the current one reaches this many bytes.
+    #[arg(long, default_value = "50000000")]
+    record_max_bytes: u64,
+    /// A new replay file is started once the current one is this old.
+    #[arg(long, default_value = "3600")]
+    record_max_secs: u64,
+    /// Games played, kills and bests of named players are kept in this JSON file.
+    #[arg(long, default_value = "multisnake_profiles.json")]
+    profiles_file: PathBuf,
+    /// Accounts registered through `/accounts/register` are kept in this JSON file.
+    #[arg(long, default_value = "multisnake_accounts.json")]
+    accounts_file: PathBuf,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+fn parse_room_password(value: &str) -> Result<(u32, String), String> {
+    let (room_id, password) = value
+        .split_once('=')
+        .ok_or("expected <room id>=<password>")?;
+    let room_id = room_id.parse().map_err(|_| "invalid room id")?;
+    Ok((room_id, password.to_string()))
+}
 
-    let (lobby_tx, _) = broadcast::channel::<LobbyUpdate>(BROADCAST_CAPACITY);
-    let (snapshot_req_tx, mut snapshot_req_rx) =
-        mpsc::unbounded_channel::<mpsc::UnboundedSender<Vec<LobbyUpdate>>>();
+fn parse_room_seed(value: &str) -> Result<(u32, u64), String> {
+    let (room_id, seed) = value.split_once('=').ok_or("expected <room id>=<seed>")?;
+    let room_id = room_id.parse().map_err(|_| "invalid room id")?;
+    let seed = seed.parse().map_err(|_| "invalid seed")?;
+    Ok((room_id, seed))
+}
 
-    let mut app = Router::new();
+fn parse_room_mode(value: &str) -> Result<(u32, GameMode), String> {
+    let (room_id, mode) = value.split_once('=').ok_or("expected <room id>=<mode>")?;
+    let room_id = room_id.parse().map_err(|_| "invalid room id")?;
+    let mode = clap::ValueEnum::from_str(mode, true)?;
+    Ok((room_id, mode))
+}
 
-    let mut managers = Vec::new();
+/// Short code for private rooms, without look-alike characters.
+fn invite_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    (0..INVITE_CODE_LEN)
+        .map(|_| ALPHABET[rand::random_range(0..ALPHABET.len())] as char)
+        .collect()
+}
 
-    for i in 1..=N_ROOMS {
-        let room_manager = Arc::new(Mutex::new(RoomManager::new(args.tick_duration_ms)));
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    logging::init(&args.log_level, args.log_format);
+    let metrics_handle = monitoring::install();
 
-        managers.push((i, room_manager.clone()));
+    let (lobby_tx, _) = broadcast::channel::<LobbyMessage>(BROADCAST_CAPACITY);
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let bans = Arc::new(BanList::default());
 
-        let ctx = Arc::new(RoomContext {
-            room_manager: room_manager.clone(),
-            lobby_tx: lobby_tx.clone(),
-            room_id: i,
-        });
+    let mut app = Router::new();
 
-        let room_manager_clone = room_manager.clone();
-        tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_millis(args.tick_duration_ms as u64));
-            loop {
-                interval.tick().await;
-                let mut room_guard = room_manager_clone.lock().await;
-                room_guard.tick();
+    let mut room_config = RoomConfig {
+        mode: GameMode::Classic,
+        tick_duration_ms: args.tick_duration_ms,
+        tick_schedule: args.tick_schedule,
+        reconnect_grace_ms: args.reconnect_grace_ms,
+        disconnect_policy: args.disconnect_policy,
+        heartbeat_interval_ms: args.heartbeat_interval_ms,
+        heartbeat_timeout_ms: args.heartbeat_timeout_ms,
+        idle_timeout_ms: args.idle_timeout_ms,
+        input_queue_depth: args.input_queue_depth,
+        max_inputs_per_tick: args.max_inputs_per_tick,
+        max_input_age_ticks: args.max_input_age_ticks,
+        kick_after_rejected_inputs: args.kick_after_rejected_inputs,
+        frame_buffer: args.frame_buffer,
+        lag_policy: args.lag_policy,
+        max_players: args.max_players,
+        bots: args.bots,
+        bot_level: args.bot_level,
+        password: None,
+        private: false,
+        requires_account: false,
+        ranked: false,
+        round_secs: args.round_secs,
+        invite_code: None,
+        seed: None,
+        recording: None,
+    };
+    if let Some(dir) = &args.record_dir {
+        let recording = RecordingConfig {
+            dir: dir.clone(),
+            max_bytes: args.record_max_bytes,
+            max_secs: args.record_max_secs,
+        };
+        match recording::prepare(&recording) {
+            Ok(()) => {
+                info!(dir = %dir.display(), "Recording the rooms.");
+                room_config.recording = Some(recording);
             }
-        });
+            Err(err) => error!(dir = %dir.display(), "Cannot record the rooms: {}", err),
+        }
+    }
 
-        let path = format!("/room/{}", i);
+    let profiles = match Profiles::load(args.profiles_file.clone()) {
+        Ok(profiles) => Arc::new(profiles),
+        Err(err) => {
+            // Starting over would overwrite them.
+            error!(path = %args.profiles_file.display(), "Cannot read the profiles: {}", err);
+            return;
+        }
+    };
+    info!(
+        path = %args.profiles_file.display(),
+        players = profiles.player_count(),
+        "Profiles loaded."
+    );
+    tokio::spawn(profiles::save_periodically(profiles.clone()));
+    let accounts = match Accounts::load(args.accounts_file.clone()) {
+        Ok(accounts) => Arc::new(accounts),
+        Err(err) => {
+            error!(path = %args.accounts_file.display(), "Cannot read the accounts: {}", err);
+            return;
+        }
+    };
+    info!(
+        path = %args.accounts_file.display(),
+        accounts = accounts.count(),
+        "Accounts loaded."
+    );
 
-        app = app.route(&path, get(socket_handlers::in_room_handler).with_state(ctx));
+    let rooms = Arc::new(Rooms::new(
+        room_config,
+        args.max_rooms.max(N_ROOMS as usize),
+        lobby_tx.clone(),
+        shutting_down.clone(),
+        bans.clone(),
+        profiles.clone(),
+        accounts.clone(),
+    ));
 
-        println!("Registered room at ws://{}{}", args.addr, path);
-    }
+    tokio::spawn(rooms.clone().close_idle_periodically());
 
-    tokio::spawn(async move {
-        while let Some(reply_tx) = snapshot_req_rx.recv().await {
-            let mut snapshot = Vec::new();
-
-            for (id, manager) in &managers {
-                let count = {
-                    let guard = manager.lock().await;
-                    guard.clients.len()
-                };
-                snapshot.push(LobbyUpdate {
-                    room_id: *id,
-                    player_count: count,
-                });
-            }
+    for i in 1..=N_ROOMS {
+        let mode = args
+            .room_modes
+            .iter()
+            .find(|(room_id, _)| *room_id == i)
+            .map_or(GameMode::Classic, |(_, mode)| *mode);
+        let mut config = rooms.template().with_mode(mode);
+        config.password = args
+            .room_passwords
+            .iter()
+            .find(|(room_id, _)| *room_id == i)
+            .map(|(_, password)| password.clone());
+        config.seed = args
+            .room_seeds
+            .iter()
+            .find(|(room_id, _)| *room_id == i)
+            .map(|(_, seed)| *seed);
+        config.private = args.private_rooms.contains(&i);
+        config.requires_account = args.account_rooms.contains(&i);
+        config.ranked = args.ranked_rooms.contains(&i);
+        if config.private {
+            config.invite_code = Some(invite_code());
+        }
 
-            let _ = reply_tx.send(snapshot);
+        let Some(ctx) = rooms.open(config) else {
+            break;
+        };
+        if let Some(code) = &ctx.config.invite_code {
+            info!(room_id = i, invite_code = %code, "Room {} is private.", i);
         }
-    });
+        info!(
+            room_id = i,
+            "Registered room at ws://{}/room/{}", args.addr, i
+        );
+    }
+
+    app = app
+        .route(
+            "/room/{id}",
+            get(socket_handlers::in_room_handler).with_state(rooms.clone()),
+        )
+        .route(
+            "/invite/{code}",
+            get(socket_handlers::invite_handler).with_state(rooms.clone()),
+        )
+        .route(
+            "/quickplay",
+            get(matchmaking::quickplay_handler).with_state(rooms.clone()),
+        );
 
     let tui_ctx = Arc::new(TuiContext {
-        lobby_tx,
-        snapshot_req_tx,
+        lobby_tx: lobby_tx.clone(),
+        shutting_down: shutting_down.clone(),
+        rooms: rooms.clone(),
+        profiles: profiles.clone(),
     });
     app = app.route(
         "/room",
         get(socket_handlers::in_tui_handler).with_state(tui_ctx),
     );
 
-    let listener = TcpListener::bind(&args.addr).await.unwrap();
-    println!("Server running on ws://{}", args.addr);
-    axum::serve(listener, app).await.unwrap();
+    let status_ctx = Arc::new(StatusContext {
+        rooms: rooms.clone(),
+        started: Instant::now(),
+        shutting_down: shutting_down.clone(),
+    });
+    app = app
+        .route(
+            "/health",
+            get(status::health_handler).with_state(status_ctx.clone()),
+        )
+        .route(
+            "/rooms",
+            get(status::rooms_handler).with_state(status_ctx.clone()),
+        )
+        .route(
+            "/rooms/{id}",
+            get(status::room_handler).with_state(status_ctx),
+        )
+        .route(
+            "/leaderboard",
+            get(profiles::leaderboard_handler).with_state(profiles.clone()),
+        )
+        .route(
+            "/profiles/{name}",
+            get(profiles::profile_handler).with_state(profiles.clone()),
+        )
+        .route(
+            "/accounts/register",
+            post(accounts::register_handler).with_state(accounts.clone()),
+        )
+        .route(
+            "/accounts/login",
+            post(accounts::login_handler).with_state(accounts),
+        );
+
+    if let Some(token) = args.admin_token.clone() {
+        let admin_ctx = Arc::new(AdminContext {
+            token,
+            rooms: rooms.clone(),
+            bans,
+            lobby_tx: lobby_tx.clone(),
+        });
+        let admin = Router::new()
+            .route(
+                "/admin/kick",
+                post(admin::kick_handler).with_state(admin_ctx.clone()),
+            )
+            .route(
+                "/admin/bans",
+                get(admin::bans_handler)
+                    .post(admin::ban_handler)
+                    .with_state(admin_ctx.clone()),
+            )
+            .route(
+                "/admin/rooms/{id}/pause",
+                post(admin::pause_handler).with_state(admin_ctx.clone()),
+            )
+            .route(
+                "/admin/rooms/{id}/resume",
+                post(admin::resume_handler).with_state(admin_ctx.clone()),
+            )
+            .route(
+                "/admin/rooms/{id}/reset",
+                post(admin::reset_handler).with_state(admin_ctx.clone()),
+            )
+            .route(
+                "/admin/notice",
+                post(admin::notice_handler).with_state(admin_ctx.clone()),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                admin_ctx,
+                admin::require_token,
+            ));
+        app = app.merge(admin);
+    }
+
+    if let Some(handle) = metrics_handle {
+        app = app.route(
+            "/metrics",
+            get(monitoring::metrics_handler).with_state(handle),
+        );
+    }
+
+    let listener = match TcpListener::bind(&args.addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(addr = %args.addr, "Could not bind: {}", err);
+            return;
+        }
+    };
+    info!("Server running on ws://{}", args.addr);
+    let served = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        let signal = shutdown::wait_for_signal().await;
+        info!(signal, "Received {}, shutting down.", signal);
+
+        shutting_down.store(true, Ordering::SeqCst);
+        let timeout = Duration::from_secs(args.shutdown_timeout_secs);
+        shutdown::drain(&rooms.handles(), &lobby_tx, timeout).await;
+    })
+    .await;
+    if let Err(err) = served {
+        error!("Server error: {}", err);
+    }
+    if let Err(err) = profiles::save_in_background(&profiles).await {
+        error!("Could not save the profiles: {}", err);
+    }
+    info!("Server stopped.");
 }
@@ -0,0 +1,104 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::rooms::Rooms;
+use multisnake_shared::GameMode;
+
+const SHUTTING_DOWN: &str = "The server is shutting down.";
+const NO_ROOM: &str = "Every room is full, try again later.";
+// Ranked rooms whose average is within this much of the player's rating are
+// as good a match, the fuller one wins.
+const RATING_BUCKET: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct QuickPlayParams {
+    /// Only consider rooms of this mode, any mode otherwise.
+    pub mode: Option<GameMode>,
+    /// Prefer ranked rooms of players rated like this one.
+    pub name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct QuickPlayMatch {
+    pub room_id: u32,
+}
+
+/// Picks the fullest public room of `mode` that still has a free spot, so
+/// players end up together instead of alone in many rooms. Ties go to the
+/// oldest room. Opens a new room when every candidate is full, closed again
+/// once it stays empty.
+///
+/// For a named player, ranked rooms come first, those whose players are rated
+/// closest to `name` ahead. Empty ranked rooms count as a close match.
+///
+/// Every room runs on this server, so latency does not tell them apart.
+pub async fn quick_play(
+    rooms: &Rooms,
+    mode: Option<GameMode>,
+    name: Option<&str>,
+) -> Result<u32, &'static str> {
+    if rooms.shutting_down() {
+        return Err(SHUTTING_DOWN);
+    }
+    let rating = name.map(|name| rooms.profiles().rating(name));
+    // Another request may be opening a room this one would fit in.
+    let _quick_play = rooms.lock_quick_play().await;
+
+    let mut best = None;
+    for ctx in rooms.all() {
+        // Guests could not join rooms that need an account.
+        if ctx.config.private || ctx.config.locked() || ctx.config.requires_account {
+            continue;
+        }
+        if mode.is_some_and(|mode| mode != ctx.config.mode) {
+            continue;
+        }
+        let Some(update) = ctx.room.snapshot().await else {
+            continue;
+        };
+        if update.player_count >= update.max_players {
+            continue;
+        }
+        let closeness = match rating {
+            Some(rating) if update.ranked => Some(Reverse(
+                update.rating.map_or(0, |average| average.abs_diff(rating)) / RATING_BUCKET,
+            )),
+            _ => None,
+        };
+        let rank = (closeness, update.player_count, Reverse(update.room_id));
+        if best.is_none_or(|best| rank > best) {
+            best = Some(rank);
+        }
+    }
+    if let Some((_, _, Reverse(room_id))) = best {
+        return Ok(room_id);
+    }
+
+    let mode = mode.unwrap_or_default();
+    let ctx = rooms.open_mode(mode).ok_or(NO_ROOM)?;
+    info!(
+        room_id = ctx.room_id,
+        mode = mode.as_str(),
+        "Opened a room for quick play."
+    );
+    Ok(ctx.room_id)
+}
+
+/// `GET /quickplay?mode=classic|fast&name=<name>`, answers the room to join.
+pub async fn quickplay_handler(
+    Query(params): Query<QuickPlayParams>,
+    State(rooms): State<Arc<Rooms>>,
+) -> Response {
+    match quick_play(&rooms, params.mode, params.name.as_deref()).await {
+        Ok(room_id) => Json(QuickPlayMatch { room_id }).into_response(),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason).into_response(),
+    }
+}
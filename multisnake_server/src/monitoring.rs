@@ -0,0 +1,98 @@
+use axum::extract::State;
+use metrics::{Unit, describe_counter, describe_gauge, describe_histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::time::Duration;
+use tracing::warn;
+
+pub const ROOM_PLAYERS: &str = "multisnake_room_players";
+pub const ROOM_BOTS: &str = "multisnake_room_bots";
+pub const TICK_DURATION: &str = "multisnake_tick_duration_seconds";
+pub const TICK_LATENESS: &str = "multisnake_tick_lateness_seconds";
+pub const TICK_OVERRUNS: &str = "multisnake_tick_overruns_total";
+pub const MESSAGES_RECEIVED: &str = "multisnake_messages_received_total";
+pub const MESSAGES_SENT: &str = "multisnake_messages_sent_total";
+pub const BYTES_SENT: &str = "multisnake_bytes_sent_total";
+pub const DECODE_ERRORS: &str = "multisnake_decode_errors_total";
+pub const REJECTED_INPUTS: &str = "multisnake_rejected_inputs_total";
+pub const LAGGED_FRAMES: &str = "multisnake_lagged_frames_total";
+pub const DEATHS: &str = "multisnake_deaths_total";
+pub const FOOD_EATEN: &str = "multisnake_food_eaten_total";
+pub const WS_CONNECTS: &str = "multisnake_ws_connects_total";
+pub const WS_DISCONNECTS: &str = "multisnake_ws_disconnects_total";
+
+// Ticks take well under a millisecond on an idle room, the budget is 100 ms by default.
+const TICK_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs the global metrics recorder. Returns `None` if it could not be installed,
+/// the metrics are then silently dropped.
+pub fn install() -> Option<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Prefix("multisnake_tick_".to_string()),
+            &TICK_BUCKETS,
+        )
+        .and_then(|builder| builder.install_recorder());
+    let handle = match handle {
+        Ok(handle) => handle,
+        Err(err) => {
+            warn!("Could not install the metrics recorder: {}", err);
+            return None;
+        }
+    };
+
+    describe_gauge!(
+        ROOM_PLAYERS,
+        "Players currently in the room, bots left out."
+    );
+    describe_gauge!(ROOM_BOTS, "Bots currently in the room.");
+    describe_histogram!(
+        TICK_DURATION,
+        Unit::Seconds,
+        "Time spent simulating a tick."
+    );
+    describe_histogram!(
+        TICK_LATENESS,
+        Unit::Seconds,
+        "How late a tick started after its scheduled time."
+    );
+    describe_counter!(
+        TICK_OVERRUNS,
+        "Ticks whose lateness plus duration exceeded the budget."
+    );
+    describe_counter!(
+        MESSAGES_RECEIVED,
+        "WebSocket messages received from players, by type."
+    );
+    describe_counter!(MESSAGES_SENT, "WebSocket messages sent to players.");
+    describe_counter!(BYTES_SENT, Unit::Bytes, "Payload bytes sent to players.");
+    describe_counter!(
+        DECODE_ERRORS,
+        "Messages from players that could not be decoded."
+    );
+    describe_counter!(REJECTED_INPUTS, "Inputs refused by the server, by reason.");
+    describe_counter!(LAGGED_FRAMES, "Frames skipped by players that fell behind.");
+    describe_counter!(DEATHS, "Snakes removed from the room, by cause.");
+    describe_counter!(FOOD_EATEN, "Food eaten in the room.");
+    describe_counter!(WS_CONNECTS, "WebSocket connections accepted.");
+    describe_counter!(WS_DISCONNECTS, "WebSocket connections closed.");
+
+    // Keeps the histograms from growing without bound between scrapes.
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    Some(handle)
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn metrics_handler(State(handle): State<PrometheusHandle>) -> String {
+    handle.render()
+}
@@ -0,0 +1,306 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::ratings::{self, INITIAL_RATING};
+use multisnake_shared::{LeaderboardEntry, Leaderboards, PlayerStats, RatingEntry};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+// Entries of the leaderboards sent to the lobby.
+pub const LOBBY_LEADERBOARD_SIZE: usize = 10;
+const MAX_LEADERBOARD_SIZE: usize = 100;
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the game of a named player ended, reported by `RoomManager::tick`.
+pub struct GameResult {
+    pub name: String,
+    pub length: usize,
+    pub survival_ms: u64,
+    pub kills: u32,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Rating {
+    rating: f64,
+    rounds: u64,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Stored {
+    all_time: HashMap<String, PlayerStats>,
+    // Days since the Unix epoch, UTC.
+    day: u64,
+    daily: HashMap<String, PlayerStats>,
+    // Of the players who finished a ranked round.
+    #[serde(default)]
+    ratings: HashMap<String, Rating>,
+}
+
+/// Stats and ratings of every named player, kept in a JSON file.
+pub struct Profiles {
+    path: PathBuf,
+    stored: Mutex<Stored>,
+    // Changed since the last save.
+    dirty: AtomicBool,
+}
+
+impl Profiles {
+    /// Reads the profiles saved at `path`, none if the file does not exist yet.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let stored = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path,
+            stored: Mutex::new(stored),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.lock().all_time.len()
+    }
+
+    pub fn record(&self, results: &[GameResult]) {
+        if results.is_empty() {
+            return;
+        }
+        let mut stored = self.lock();
+        for result in results {
+            update(
+                stored.all_time.entry(result.name.clone()).or_default(),
+                result,
+            );
+            update(stored.daily.entry(result.name.clone()).or_default(), result);
+        }
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Rates the players of a ranked round from its standings, best first,
+    /// returning their new ratings.
+    pub fn record_round(&self, standings: &[Vec<String>]) -> Vec<(String, u32)> {
+        let mut stored = self.lock();
+        let players: Vec<(&String, usize)> = standings
+            .iter()
+            .enumerate()
+            .flat_map(|(place, names)| names.iter().map(move |name| (name, place)))
+            .collect();
+        let current: Vec<(f64, usize)> = players
+            .iter()
+            .map(|(name, place)| {
+                let rating = stored
+                    .ratings
+                    .get(*name)
+                    .map_or(INITIAL_RATING, |rating| rating.rating);
+                (rating, *place)
+            })
+            .collect();
+
+        let rated = ratings::rate(&current);
+        let mut updated = Vec::new();
+        for ((name, _), rating) in players.into_iter().zip(rated) {
+            let entry = stored.ratings.entry(name.clone()).or_insert(Rating {
+                rating: INITIAL_RATING,
+                rounds: 0,
+            });
+            entry.rating = rating;
+            entry.rounds += 1;
+            updated.push((name.clone(), rating.round() as u32));
+        }
+        self.dirty.store(true, Ordering::SeqCst);
+        updated
+    }
+
+    /// Rating of `name`, the initial one until it finished a ranked round.
+    pub fn rating(&self, name: &str) -> u32 {
+        self.lock()
+            .ratings
+            .get(name)
+            .map_or(INITIAL_RATING, |rating| rating.rating)
+            .round() as u32
+    }
+
+    /// All-time and today's stats of `name`.
+    pub fn get(&self, name: &str) -> Option<(PlayerStats, PlayerStats)> {
+        let stored = self.lock();
+        let all_time = *stored.all_time.get(name)?;
+        Some((
+            all_time,
+            stored.daily.get(name).copied().unwrap_or_default(),
+        ))
+    }
+
+    /// Rating of `name` if it finished a ranked round.
+    pub fn rated(&self, name: &str) -> Option<RatingEntry> {
+        let stored = self.lock();
+        let rating = stored.ratings.get(name)?;
+        Some(rating_entry(name, rating))
+    }
+
+    pub fn leaderboards(&self, size: usize) -> Leaderboards {
+        let stored = self.lock();
+        let mut ratings: Vec<RatingEntry> = stored
+            .ratings
+            .iter()
+            .map(|(name, rating)| rating_entry(name, rating))
+            .collect();
+        ratings.sort_by(|a, b| b.rating.cmp(&a.rating).then_with(|| a.name.cmp(&b.name)));
+        ratings.truncate(size);
+        Leaderboards {
+            all_time: leaderboard(&stored.all_time, size),
+            daily: leaderboard(&stored.daily, size),
+            ratings,
+        }
+    }
+
+    /// Writes the profiles if anything changed. The file is replaced at once,
+    /// a crash while saving keeps the previous one.
+    ///
+    /// Blocks on the disk, run it off the async threads with `save_in_background`.
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        // Copied so that rooms recording results do not wait for the encoding.
+        let stored = self.lock().clone();
+        let tmp = self.path.with_extension("tmp");
+        let written = serde_json::to_vec(&stored)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if written.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        written
+    }
+
+    /// Locks the profiles, starting a new daily leaderboard once the day changed.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Stored> {
+        let mut stored = self.stored.lock().unwrap_or_else(|err| err.into_inner());
+        let today = today();
+        if stored.day != today {
+            stored.day = today;
+            stored.daily.clear();
+        }
+        stored
+    }
+}
+
+/// `Profiles::save` on the blocking threads.
+pub async fn save_in_background(profiles: &Arc<Profiles>) -> io::Result<()> {
+    let profiles = profiles.clone();
+    tokio::task::spawn_blocking(move || profiles.save())
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Saves the profiles every `SAVE_INTERVAL`, the server saves them once more when it stops.
+pub async fn save_periodically(profiles: Arc<Profiles>) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = save_in_background(&profiles).await {
+            warn!("Could not save the profiles: {}", err);
+        }
+    }
+}
+
+fn update(stats: &mut PlayerStats, result: &GameResult) {
+    stats.games_played += 1;
+    stats.total_kills += result.kills as u64;
+    stats.best_length = stats.best_length.max(result.length);
+    stats.best_survival_ms = stats.best_survival_ms.max(result.survival_ms);
+}
+
+fn rating_entry(name: &str, rating: &Rating) -> RatingEntry {
+    RatingEntry {
+        name: name.to_string(),
+        rating: rating.rating.round() as u32,
+        rounds: rating.rounds,
+    }
+}
+
+fn leaderboard(players: &HashMap<String, PlayerStats>, size: usize) -> Vec<LeaderboardEntry> {
+    let mut entries: Vec<LeaderboardEntry> = players
+        .iter()
+        .map(|(name, stats)| LeaderboardEntry {
+            name: name.clone(),
+            stats: *stats,
+        })
+        .collect();
+    entries.sort_by(|a, b| {
+        let key = |entry: &LeaderboardEntry| {
+            (
+                entry.stats.best_length,
+                entry.stats.total_kills,
+                entry.stats.best_survival_ms,
+            )
+        };
+        key(b).cmp(&key(a)).then_with(|| a.name.cmp(&b.name))
+    });
+    entries.truncate(size);
+    entries
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECS_PER_DAY)
+}
+
+#[derive(Deserialize)]
+pub struct LeaderboardParams {
+    /// Entries per leaderboard, 10 by default.
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct ProfileStatus {
+    pub name: String,
+    pub all_time: PlayerStats,
+    pub today: PlayerStats,
+    // `None` until the player finished a ranked round.
+    pub rating: Option<RatingEntry>,
+}
+
+pub async fn leaderboard_handler(
+    State(profiles): State<Arc<Profiles>>,
+    Query(params): Query<LeaderboardParams>,
+) -> Json<Leaderboards> {
+    let size = params
+        .limit
+        .unwrap_or(LOBBY_LEADERBOARD_SIZE)
+        .min(MAX_LEADERBOARD_SIZE);
+    Json(profiles.leaderboards(size))
+}
+
+pub async fn profile_handler(
+    State(profiles): State<Arc<Profiles>>,
+    Path(name): Path<String>,
+) -> Response {
+    match profiles.get(&name) {
+        Some((all_time, today)) => Json(ProfileStatus {
+            rating: profiles.rated(&name),
+            name,
+            all_time,
+            today,
+        })
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
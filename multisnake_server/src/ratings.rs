@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+// Most a player can win or lose in a single round.
+const K_FACTOR: f64 = 32.0;
+// A ranked room starts a round once this many logged-in players are in.
+pub const MIN_ROUND_PLAYERS: usize = 2;
+
+/// Elo ratings after a round, from the players' current ratings and their
+/// places, 0 being the winner. Players sharing a place drew against each other.
+///
+/// Every player is compared to every other one. The changes are scaled by the
+/// number of opponents, so a round weighs the same however many played it.
+pub fn rate(players: &[(f64, usize)]) -> Vec<f64> {
+    if players.len() < 2 {
+        return players.iter().map(|(rating, _)| *rating).collect();
+    }
+    let opponents = (players.len() - 1) as f64;
+    players
+        .iter()
+        .enumerate()
+        .map(|(i, &(rating, place))| {
+            let surprise: f64 = players
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, &(other, other_place))| {
+                    let score = match place.cmp(&other_place) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    let expected = 1.0 / (1.0 + 10f64.powf((other - rating) / 400.0));
+                    score - expected
+                })
+                .sum();
+            rating + K_FACTOR * surprise / opponents
+        })
+        .collect()
+}
+
+/// A round of a ranked room. It is played by the logged-in players in the room
+/// when it starts, and ends once at most one of them is left or time is up.
+/// Players joining meanwhile play unrated until the next round.
+pub struct Round {
+    pub started_tick: u64,
+    // Names of the snakes still in the round.
+    alive: HashMap<Uuid, String>,
+    // Names of the snakes out of the round, grouped by the tick they died on,
+    // first out first.
+    out: Vec<Vec<String>>,
+}
+
+impl Round {
+    pub fn start(tick: u64, players: HashMap<Uuid, String>) -> Self {
+        Self {
+            started_tick: tick,
+            alive: players,
+            out: Vec::new(),
+        }
+    }
+
+    pub fn players(&self) -> usize {
+        self.alive.len() + self.out.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// Takes the snakes that died on the same tick out of the round.
+    pub fn eliminate(&mut self, died: &[Uuid]) {
+        let names: Vec<String> = died.iter().filter_map(|id| self.alive.remove(id)).collect();
+        if !names.is_empty() {
+            self.out.push(names);
+        }
+    }
+
+    pub fn is_over(&self, tick: u64, max_ticks: u64) -> bool {
+        self.alive.len() <= 1 || tick - self.started_tick >= max_ticks
+    }
+
+    /// Names by place, best first, players sharing a place in the same group.
+    /// Survivors come first, the longer snakes ahead, then the others from the
+    /// last one out to the first.
+    pub fn standings(self, length: impl Fn(&Uuid) -> usize) -> Vec<Vec<String>> {
+        let mut survivors: Vec<(usize, String)> = self
+            .alive
+            .into_iter()
+            .map(|(id, name)| (length(&id), name))
+            .collect();
+        survivors.sort_by_key(|(length, _)| std::cmp::Reverse(*length));
+
+        let mut standings: Vec<Vec<String>> = Vec::new();
+        let mut previous = None;
+        for (length, name) in survivors {
+            match standings.last_mut() {
+                Some(place) if previous == Some(length) => place.push(name),
+                _ => standings.push(vec![name]),
+            }
+            previous = Some(length);
+        }
+        standings.extend(self.out.into_iter().rev());
+        standings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn start(names: &[&str]) -> Round {
+        let players = names
+            .iter()
+            .enumerate()
+            .map(|(n, name)| (id(n as u128), name.to_string()))
+            .collect();
+        Round::start(10, players)
+    }
+
+    /// Standings with the names of each place sorted, survivors come out of a map.
+    fn sorted(mut standings: Vec<Vec<String>>) -> Vec<Vec<String>> {
+        for place in &mut standings {
+            place.sort();
+        }
+        standings
+    }
+
+    #[test]
+    fn two_player_win_and_loss_are_symmetric() {
+        let rated = rate(&[(INITIAL_RATING, 0), (INITIAL_RATING, 1)]);
+        assert_eq!(rated, vec![INITIAL_RATING + 16.0, INITIAL_RATING - 16.0]);
+
+        let rated = rate(&[(1400.0, 0), (1600.0, 1)]);
+        let won = rated[0] - 1400.0;
+        let lost = 1600.0 - rated[1];
+        assert!(won > 16.0 && won < K_FACTOR);
+        assert!((won - lost).abs() < 1e-9);
+
+        let drawn = rate(&[(INITIAL_RATING, 0), (INITIAL_RATING, 0)]);
+        assert_eq!(drawn, vec![INITIAL_RATING, INITIAL_RATING]);
+    }
+
+    #[test]
+    fn changes_are_scaled_by_the_number_of_opponents() {
+        let players: Vec<(f64, usize)> = (0..5).map(|place| (INITIAL_RATING, place)).collect();
+        let rated = rate(&players);
+        // Beating four players weighs as much as beating one.
+        assert!((rated[0] - (INITIAL_RATING + K_FACTOR / 2.0)).abs() < 1e-9);
+        assert!((rated[4] - (INITIAL_RATING - K_FACTOR / 2.0)).abs() < 1e-9);
+        assert!((rated[2] - INITIAL_RATING).abs() < 1e-9);
+        assert!((rated.iter().sum::<f64>() - 5.0 * INITIAL_RATING).abs() < 1e-9);
+
+        assert_eq!(rate(&[(1234.0, 0)]), vec![1234.0]);
+    }
+
+    #[test]
+    fn survivors_are_placed_by_length_and_tie_on_equal_lengths() {
+        let mut round = start(&["ann", "ben", "cat", "dan", "eve"]);
+        round.eliminate(&[id(3)]);
+        round.eliminate(&[id(4)]);
+        assert!(!round.is_over(20, 100));
+        let lengths = HashMap::from([(id(0), 5), (id(1), 5), (id(2), 3)]);
+        let standings = round.standings(|id| lengths[id]);
+        assert_eq!(
+            sorted(standings),
+            vec![
+                vec!["ann".to_string(), "ben".to_string()],
+                vec!["cat".to_string()],
+                vec!["eve".to_string()],
+                vec!["dan".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn round_ends_when_one_player_is_left_or_time_is_up() {
+        let names: Vec<String> = (0..MIN_ROUND_PLAYERS).map(|n| n.to_string()).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let mut round = start(&names);
+        assert_eq!(round.players(), MIN_ROUND_PLAYERS);
+        assert!(!round.is_over(20, 100));
+        round.eliminate(&[id(0)]);
+        assert!(round.is_over(20, 100));
+        assert_eq!(round.players(), MIN_ROUND_PLAYERS);
+
+        let round = start(&names);
+        assert!(!round.is_over(109, 100));
+        assert!(round.is_over(110, 100));
+    }
+
+    #[test]
+    fn late_joiners_are_not_in_the_round() {
+        let mut round = start(&["ann", "ben", "cat"]);
+        let late = id(99);
+        round.eliminate(&[late]);
+        assert_eq!(round.players(), 3);
+        assert!(!round.is_over(20, 100));
+
+        round.eliminate(&[id(0), late]);
+        let standings = round.standings(|_| 4);
+        assert_eq!(
+            sorted(standings),
+            vec![
+                vec!["ben".to_string(), "cat".to_string()],
+                vec!["ann".to_string()],
+            ]
+        );
+    }
+}
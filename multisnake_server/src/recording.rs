@@ -0,0 +1,290 @@
+use flate2::{Compression, write::GzEncoder};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Ticks queued for the writer past which the room drops them, about 20 seconds
+// at the default tick duration.
+const MAX_QUEUED_TICKS: usize = 200;
+
+use multisnake_shared::GameMode;
+use multisnake_shared::replay::{self, KEYFRAME_INTERVAL_TICKS, ReplayRecord};
+
+/// Where replay files go and when a new one is started.
+#[derive(Clone)]
+pub struct RecordingConfig {
+    pub dir: PathBuf,
+    // Compressed size past which a new file is started.
+    pub max_bytes: u64,
+    // Age past which a new file is started.
+    pub max_secs: u64,
+}
+
+/// Counts what goes through to the file, the size the encoder buffers aside.
+struct CountingWriter {
+    file: File,
+    written: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+struct ReplayFile {
+    encoder: GzEncoder<CountingWriter>,
+    path: PathBuf,
+    opened: Instant,
+}
+
+/// What the room sends its writer after each tick.
+struct Job {
+    tick: u64,
+    record: ReplayRecord,
+    // The room after the tick, when the writer asked for one or on keyframe ticks.
+    keyframe: Option<ReplayRecord>,
+    // The room has no snake left, the file is closed.
+    empty: bool,
+}
+
+/// Writes the replay files of one room. A file covers the room from the
+/// first tick with snakes until it is empty again or the file is full.
+///
+/// Compressing and writing happen on a thread of their own, so that a slow
+/// disk does not hold the room's ticks up. When the disk falls too far behind
+/// the ticks are dropped instead, ending the file, and a new one starts once
+/// the writer caught up.
+pub struct Recorder {
+    room_id: u32,
+    tx: Option<mpsc::SyncSender<Job>>,
+    // Set by the writer while it has no file open, the next keyframe starts one.
+    keyframe_wanted: Arc<AtomicBool>,
+    // The last tick was dropped, the warning is logged once per backlog.
+    dropping: bool,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn new(
+        config: RecordingConfig,
+        room_id: u32,
+        mode: GameMode,
+        tick_duration_ms: u32,
+        seed: u64,
+    ) -> Self {
+        let (tx, rx) = mpsc::sync_channel(MAX_QUEUED_TICKS);
+        let keyframe_wanted = Arc::new(AtomicBool::new(true));
+        let mut writer = Writer {
+            config,
+            room_id,
+            mode,
+            tick_duration_ms,
+            seed,
+            file: None,
+            last_tick: None,
+            keyframe_wanted: keyframe_wanted.clone(),
+        };
+        let spawned = thread::Builder::new()
+            .name(format!("replay-room-{}", room_id))
+            .spawn(move || writer.run(rx));
+        let (tx, writer) = match spawned {
+            Ok(handle) => (Some(tx), Some(handle)),
+            Err(err) => {
+                warn!(room_id, "Cannot record the room: {}", err);
+                (None, None)
+            }
+        };
+        Self {
+            room_id,
+            tx,
+            keyframe_wanted,
+            dropping: false,
+            writer,
+        }
+    }
+
+    /// Queues the record of `tick` for the writer. `keyframe` gives the room
+    /// as it is after the tick, to start a file with and to seek from, and is
+    /// only called when needed. `empty` closes the file.
+    pub fn record(
+        &mut self,
+        tick: u64,
+        record: ReplayRecord,
+        empty: bool,
+        keyframe: impl FnOnce() -> ReplayRecord,
+    ) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let wanted = !empty && self.keyframe_wanted.load(Ordering::SeqCst);
+        let job = Job {
+            tick,
+            record,
+            keyframe: (wanted || tick.is_multiple_of(KEYFRAME_INTERVAL_TICKS)).then(keyframe),
+            empty,
+        };
+        match tx.try_send(job) {
+            Ok(()) => self.dropping = false,
+            // The writer ends the file at the gap, the next file starts from a keyframe.
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.keyframe_wanted.store(true, Ordering::SeqCst);
+                if !self.dropping {
+                    warn!(
+                        room_id = self.room_id,
+                        tick, "The replay writer fell behind, dropping ticks."
+                    );
+                    self.dropping = true;
+                }
+            }
+            // The writer gave up after an I/O error.
+            Err(mpsc::TrySendError::Disconnected(_)) => self.tx = None,
+        }
+    }
+}
+
+impl Drop for Recorder {
+    /// Lets the writer finish the file on a blocking thread, which the runtime
+    /// waits for before the server exits, instead of on the room's worker.
+    fn drop(&mut self) {
+        self.tx = None;
+        let Some(writer) = self.writer.take() else {
+            return;
+        };
+        let room_id = self.room_id;
+        let join = move || {
+            if writer.join().is_err() {
+                warn!(room_id, "The replay writer panicked.");
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(join)),
+            Err(_) => join(),
+        }
+    }
+}
+
+/// Owns the room's replay file on the recording thread.
+struct Writer {
+    config: RecordingConfig,
+    room_id: u32,
+    mode: GameMode,
+    tick_duration_ms: u32,
+    seed: u64,
+    file: Option<ReplayFile>,
+    // Tick of the last job, the room dropped the ones in between if it is not the previous one.
+    last_tick: Option<u64>,
+    keyframe_wanted: Arc<AtomicBool>,
+}
+
+impl Writer {
+    /// Writes until the room drops its recorder or an I/O error, which stops
+    /// the recording so that a full disk does not log every tick.
+    fn run(&mut self, rx: mpsc::Receiver<Job>) {
+        for job in rx {
+            if let Err(err) = self.write(job) {
+                warn!(room_id = self.room_id, "Stopped recording: {}", err);
+                self.file = None;
+                return;
+            }
+        }
+        if let Err(err) = self.close() {
+            warn!(
+                room_id = self.room_id,
+                "Could not finish the replay: {}", err
+            );
+        }
+    }
+
+    fn write(&mut self, job: Job) -> io::Result<()> {
+        // A file with a gap could not be played back, it ends before the gap.
+        if self.last_tick.is_some_and(|last| job.tick != last + 1) {
+            self.close()?;
+        }
+        self.last_tick = Some(job.tick);
+        if let Some(file) = self.file.as_mut() {
+            write_record(&mut file.encoder, &job.record)?;
+            let full = file.encoder.get_ref().written >= self.config.max_bytes
+                || file.opened.elapsed() >= Duration::from_secs(self.config.max_secs);
+            if job.empty || full {
+                self.close()?;
+            } else if let Some(keyframe) = &job.keyframe
+                && job.tick.is_multiple_of(KEYFRAME_INTERVAL_TICKS)
+            {
+                write_record(&mut file.encoder, keyframe)?;
+                // Makes the file readable up to here while it is being written.
+                file.encoder.flush()?;
+            }
+            return Ok(());
+        }
+        // Ticks until the room sends a keyframe are left out, the file starts from it.
+        let (false, Some(keyframe)) = (job.empty, &job.keyframe) else {
+            return Ok(());
+        };
+
+        let started_unix_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = self.config.dir.join(format!(
+            "room-{}-{}-{}.{}",
+            self.room_id,
+            started_unix_secs,
+            job.tick,
+            replay::EXTENSION
+        ));
+        let writer = CountingWriter {
+            file: File::create(&path)?,
+            written: 0,
+        };
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        let header = ReplayRecord::Header {
+            room_id: self.room_id,
+            mode: self.mode,
+            tick_duration_ms: self.tick_duration_ms,
+            seed: self.seed,
+            started_unix_secs,
+        };
+        write_record(&mut encoder, &header)?;
+        write_record(&mut encoder, keyframe)?;
+        encoder.flush()?;
+        info!(path = %path.display(), "Recording the room.");
+        self.file = Some(ReplayFile {
+            encoder,
+            path,
+            opened: Instant::now(),
+        });
+        self.keyframe_wanted.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            self.keyframe_wanted.store(true, Ordering::SeqCst);
+            file.encoder.finish()?;
+            info!(path = %file.path.display(), "Replay saved.");
+        }
+        Ok(())
+    }
+}
+
+/// Creates the replay directory, so that rooms do not each fail on it.
+pub fn prepare(config: &RecordingConfig) -> io::Result<()> {
+    fs::create_dir_all(&config.dir)
+}
+
+fn write_record(encoder: &mut GzEncoder<CountingWriter>, record: &ReplayRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut *encoder, record)?;
+    encoder.write_all(b"\n")
+}
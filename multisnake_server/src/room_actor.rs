@@ -0,0 +1,766 @@
+use axum::extract::ws::Message;
+use metrics::{counter, gauge, histogram};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{self, Instant};
+use tracing::{Instrument, debug, info, info_span, warn};
+use uuid::Uuid;
+
+use crate::monitoring;
+use crate::profiles::{self, Profiles};
+use crate::room_manager::{DeathCause, InputRejection, PlayerFilter, RoomManager, encode};
+use crate::status::RoomStatus;
+use multisnake_shared::{Direction, LobbyMessage, LobbyUpdate};
+
+const COMMAND_CAPACITY: usize = 1024;
+const KICK_REASON_REJECTED_INPUTS: &str = "Too many invalid inputs.";
+// Weight of the newest sample in the tick timing averages.
+const EWMA_WEIGHT: f64 = 0.1;
+
+/// Timing of a room's ticks as measured by its task.
+pub struct TickStats {
+    pub ticks: u64,
+    // Ticks whose lateness plus duration exceeded the tick budget.
+    pub overruns: u64,
+    // Average time between the starts of consecutive ticks.
+    pub avg_interval_ms: f64,
+    // Average time spent inside `RoomManager::tick`.
+    pub avg_duration_ms: f64,
+    last_start: Option<Instant>,
+}
+
+impl TickStats {
+    fn new(tick_duration_ms: u32) -> Self {
+        Self {
+            ticks: 0,
+            overruns: 0,
+            avg_interval_ms: tick_duration_ms as f64,
+            avg_duration_ms: 0.0,
+            last_start: None,
+        }
+    }
+
+    fn record(&mut self, started: Instant, duration: Duration) {
+        if let Some(last_start) = self.last_start {
+            let interval_ms = started.duration_since(last_start).as_secs_f64() * 1000.0;
+            self.avg_interval_ms += EWMA_WEIGHT * (interval_ms - self.avg_interval_ms);
+        }
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        self.avg_duration_ms += EWMA_WEIGHT * (duration_ms - self.avg_duration_ms);
+        self.last_start = Some(started);
+        self.ticks += 1;
+    }
+}
+
+/// What a socket gets back when it joins a room.
+pub struct Joined {
+    pub client_id: Uuid,
+    // `OnJoin` snapshot, to be sent before anything from `frames`.
+    pub init: Message,
+    pub frames: broadcast::Receiver<Message>,
+}
+
+/// Everything a room task can be asked to do.
+pub enum RoomCommand {
+    /// Adds a player, or gives the snake owning `session_token` back to it.
+    Join {
+        session_token: Option<Uuid>,
+        connection_id: Uuid,
+        name: Option<String>,
+        // `name` is the account the player logged in to.
+        account: bool,
+        ip: Option<IpAddr>,
+        tx: UnboundedSender<Message>,
+        reply: oneshot::Sender<Joined>,
+    },
+    /// Full snapshot for a client that fell too far behind.
+    Keyframe {
+        client_id: Uuid,
+        reply: oneshot::Sender<Message>,
+    },
+    /// The socket `connection_id` of `client_id` went away.
+    Leave {
+        client_id: Uuid,
+        connection_id: Uuid,
+    },
+    Input {
+        client_id: Uuid,
+        dir: Direction,
+        tick: u64,
+    },
+    /// The socket layer refused something the client sent.
+    Reject {
+        client_id: Uuid,
+        reason: InputRejection,
+    },
+    RecordRtt {
+        client_id: Uuid,
+        rtt_ms: u32,
+    },
+    RecordBacklog {
+        client_id: Uuid,
+        backlog: usize,
+    },
+    RecordLag {
+        client_id: Uuid,
+        skipped: u64,
+    },
+    Evict {
+        client_id: Uuid,
+        cause: DeathCause,
+    },
+    Snapshot {
+        reply: oneshot::Sender<LobbyUpdate>,
+    },
+    /// Players with a socket, for the shutdown to know when games are over.
+    Connected {
+        reply: oneshot::Sender<usize>,
+    },
+    /// Configuration and players, for the HTTP status routes.
+    Status {
+        reply: oneshot::Sender<RoomStatus>,
+    },
+    /// Kicks the matching players, replying with their ids.
+    Kick {
+        filter: PlayerFilter,
+        reason: String,
+        reply: oneshot::Sender<Vec<Uuid>>,
+    },
+    /// Freezes or unfreezes every snake.
+    Pause {
+        paused: bool,
+    },
+    /// Kicks everyone and starts the room over.
+    Reset {
+        reason: String,
+    },
+    /// Shows a message from the operators to everyone in the room.
+    Notice {
+        message: String,
+    },
+    /// Refuses further joins and warns the players the server goes down in `seconds`.
+    Shutdown {
+        reason: String,
+        seconds: u32,
+    },
+    /// Disconnects everyone and ends the room task.
+    Stop {
+        reply: oneshot::Sender<()>,
+    },
+}
+
+/// Cheap to clone handle used to talk to a room task.
+#[derive(Clone)]
+pub struct RoomHandle {
+    pub room_id: u32,
+    // Hidden from the lobby.
+    pub private: bool,
+    tx: mpsc::Sender<RoomCommand>,
+}
+
+impl RoomHandle {
+    /// Returns `false` if the room task is gone.
+    pub async fn send(&self, cmd: RoomCommand) -> bool {
+        self.tx.send(cmd).await.is_ok()
+    }
+
+    pub async fn join(
+        &self,
+        session_token: Option<Uuid>,
+        connection_id: Uuid,
+        name: Option<String>,
+        account: bool,
+        ip: Option<IpAddr>,
+        tx: UnboundedSender<Message>,
+    ) -> Option<Joined> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Join {
+            session_token,
+            connection_id,
+            name,
+            account,
+            ip,
+            tx,
+            reply,
+        })
+        .await;
+        reply_rx.await.ok()
+    }
+
+    pub async fn keyframe(&self, client_id: Uuid) -> Option<Message> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Keyframe { client_id, reply }).await;
+        reply_rx.await.ok()
+    }
+
+    pub async fn snapshot(&self) -> Option<LobbyUpdate> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Snapshot { reply }).await;
+        reply_rx.await.ok()
+    }
+
+    pub async fn connected(&self) -> Option<usize> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Connected { reply }).await;
+        reply_rx.await.ok()
+    }
+
+    pub async fn kick(&self, filter: PlayerFilter, reason: String) -> Option<Vec<Uuid>> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Kick {
+            filter,
+            reason,
+            reply,
+        })
+        .await;
+        reply_rx.await.ok()
+    }
+
+    pub async fn status(&self) -> Option<RoomStatus> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Status { reply }).await;
+        reply_rx.await.ok()
+    }
+
+    /// Resolves once the room task has finished.
+    pub async fn stop(&self) {
+        let (reply, reply_rx) = oneshot::channel();
+        self.send(RoomCommand::Stop { reply }).await;
+        let _ = reply_rx.await;
+    }
+}
+
+/// Spawns the task owning `manager`. It ticks the room and applies commands
+/// one at a time, so the room state needs no lock.
+pub fn spawn_room(
+    room_id: u32,
+    manager: RoomManager,
+    lobby_tx: broadcast::Sender<LobbyMessage>,
+    profiles: Arc<Profiles>,
+) -> RoomHandle {
+    let private = manager.config.private;
+    let (tx, rx) = mpsc::channel(COMMAND_CAPACITY);
+    tokio::spawn(
+        run(room_id, manager, rx, lobby_tx, profiles).instrument(info_span!("room", room_id)),
+    );
+    RoomHandle {
+        room_id,
+        private,
+        tx,
+    }
+}
+
+async fn run(
+    room_id: u32,
+    mut manager: RoomManager,
+    mut rx: mpsc::Receiver<RoomCommand>,
+    lobby_tx: broadcast::Sender<LobbyMessage>,
+    profiles: Arc<Profiles>,
+) {
+    let budget = Duration::from_millis(manager.config.tick_duration_ms as u64);
+    let mut interval = time::interval(budget);
+    interval.set_missed_tick_behavior(manager.config.tick_schedule.into());
+    let mut stats = TickStats::new(manager.config.tick_duration_ms);
+    let mut player_count = manager.player_count();
+    let mut bot_count = manager.bot_count();
+    let room = room_id.to_string();
+    info!(
+        seed = manager.seed,
+        mode = manager.config.mode.as_str(),
+        "Room started."
+    );
+    gauge!(monitoring::ROOM_PLAYERS, "room" => room.clone()).set(player_count as f64);
+    gauge!(monitoring::ROOM_BOTS, "room" => room.clone()).set(bot_count as f64);
+    // Shows rooms opened while the server runs to the selectors already connected.
+    if !manager.config.private {
+        let _ = lobby_tx.send(LobbyMessage::RoomUpdate(lobby_update(room_id, &manager)));
+    }
+
+    loop {
+        tokio::select! {
+            scheduled = interval.tick() => {
+                let started = Instant::now();
+                // Time spent on commands or waiting for the runtime before the tick could start.
+                let lateness = started.duration_since(scheduled);
+
+                manager.measured_tick_ms = stats.avg_interval_ms as f32;
+                let summary = manager.tick();
+
+                let duration = started.elapsed();
+                stats.record(started, duration);
+
+                histogram!(monitoring::TICK_DURATION, "room" => room.clone()).record(duration);
+                histogram!(monitoring::TICK_LATENESS, "room" => room.clone()).record(lateness);
+                counter!(monitoring::FOOD_EATEN, "room" => room.clone())
+                    .increment(summary.food_eaten as u64);
+                for cause in summary.deaths {
+                    counter!(monitoring::DEATHS, "room" => room.clone(), "cause" => cause.as_str())
+                        .increment(1);
+                }
+                if !summary.results.is_empty() {
+                    profiles.record(&summary.results);
+                }
+                if let Some(standings) = &summary.standings {
+                    for (name, rating) in profiles.record_round(standings) {
+                        manager.set_rating(&name, rating);
+                    }
+                    if !manager.config.private {
+                        let _ = lobby_tx.send(LobbyMessage::RoomUpdate(lobby_update(room_id, &manager)));
+                    }
+                }
+                if !summary.results.is_empty() || summary.standings.is_some() {
+                    let leaderboards = profiles.leaderboards(profiles::LOBBY_LEADERBOARD_SIZE);
+                    let _ = lobby_tx.send(LobbyMessage::Leaderboard(leaderboards));
+                }
+
+                if lateness + duration > budget {
+                    stats.overruns += 1;
+                    counter!(monitoring::TICK_OVERRUNS, "room" => room.clone()).increment(1);
+                    warn!(
+                        tick = manager.sim.tick_count,
+                        ?budget,
+                        ?lateness,
+                        ?duration,
+                        overruns = stats.overruns,
+                        "Tick overran its budget."
+                    );
+                }
+            }
+            cmd = rx.recv() => {
+                let Some(cmd) = cmd else { break };
+                if !handle_command(&mut manager, room_id, &profiles, cmd) {
+                    break;
+                }
+            }
+        }
+
+        // Joins, leaves and deaths all change the counts.
+        if (manager.player_count(), manager.bot_count()) != (player_count, bot_count) {
+            player_count = manager.player_count();
+            bot_count = manager.bot_count();
+            gauge!(monitoring::ROOM_PLAYERS, "room" => room.clone()).set(player_count as f64);
+            gauge!(monitoring::ROOM_BOTS, "room" => room.clone()).set(bot_count as f64);
+            if !manager.config.private {
+                let _ = lobby_tx.send(LobbyMessage::RoomUpdate(lobby_update(room_id, &manager)));
+            }
+        }
+    }
+    debug!("Room task stopped.");
+}
+
+/// What the room selector shows of the room.
+pub fn lobby_update(room_id: u32, manager: &RoomManager) -> LobbyUpdate {
+    LobbyUpdate {
+        room_id,
+        player_count: manager.player_count(),
+        bots: manager.bot_count(),
+        max_players: manager.config.max_players,
+        mode: manager.config.mode,
+        locked: manager.config.locked(),
+        requires_account: manager.config.requires_account,
+        ranked: manager.config.ranked,
+        rating: manager.average_rating(),
+    }
+}
+
+/// Applies a single command to the room. Returns `false` once the room was stopped.
+pub fn handle_command(
+    manager: &mut RoomManager,
+    room_id: u32,
+    profiles: &Profiles,
+    cmd: RoomCommand,
+) -> bool {
+    match cmd {
+        RoomCommand::Join {
+            session_token,
+            connection_id,
+            name,
+            account,
+            ip,
+            tx,
+            reply,
+        } => {
+            // Dropping `reply` turns the socket away.
+            if manager.closing {
+                debug!(%connection_id, "Refused a join while closing.");
+                return true;
+            }
+
+            let reconnected = session_token
+                .and_then(|token| manager.reconnect_client(token, connection_id, ip, tx.clone()));
+
+            let client_id = match reconnected {
+                Some(client_id) => {
+                    info!(%client_id, %connection_id, "Player reconnected.");
+                    client_id
+                }
+                None if manager.player_count() >= manager.config.max_players => {
+                    debug!(%connection_id, "Refused a join, the room is full.");
+                    return true;
+                }
+                None => {
+                    let client_id = Uuid::new_v4();
+                    manager.add_client(
+                        client_id,
+                        connection_id,
+                        name.clone(),
+                        account,
+                        ip,
+                        tx.clone(),
+                    );
+                    if manager.config.ranked
+                        && account
+                        && let Some(name) = &name
+                    {
+                        manager.set_rating(name, profiles.rating(name));
+                    }
+                    info!(
+                        %client_id,
+                        %connection_id,
+                        ?name,
+                        players = manager.player_count(),
+                        "Player joined."
+                    );
+                    client_id
+                }
+            };
+
+            // Subscribing now means the socket gets every frame after the snapshot.
+            if let Some(init) = encode(&manager.new_init_message(client_id)) {
+                let _ = reply.send(Joined {
+                    client_id,
+                    init,
+                    frames: manager.frames_tx.subscribe(),
+                });
+            }
+        }
+        RoomCommand::Keyframe { client_id, reply } => {
+            if manager.clients.contains_key(&client_id)
+                && let Some(keyframe) = encode(&manager.new_init_message(client_id))
+            {
+                let _ = reply.send(keyframe);
+            }
+        }
+        RoomCommand::Leave {
+            client_id,
+            connection_id,
+        } => manager.disconnect_client(&client_id, connection_id),
+        RoomCommand::Input {
+            client_id,
+            dir,
+            tick,
+        } => {
+            if let Err(reason) = manager.queue_move(&client_id, dir, tick) {
+                reject_input(manager, room_id, client_id, reason);
+            }
+        }
+        RoomCommand::Reject { client_id, reason } => {
+            reject_input(manager, room_id, client_id, reason)
+        }
+        RoomCommand::RecordRtt { client_id, rtt_ms } => manager.record_rtt(&client_id, rtt_ms),
+        RoomCommand::RecordBacklog { client_id, backlog } => {
+            manager.record_backlog(&client_id, backlog)
+        }
+        RoomCommand::RecordLag { client_id, skipped } => manager.record_lag(&client_id, skipped),
+        RoomCommand::Evict { client_id, cause } => manager.evict_client(&client_id, cause),
+        RoomCommand::Snapshot { reply } => {
+            let _ = reply.send(lobby_update(room_id, manager));
+        }
+        RoomCommand::Connected { reply } => {
+            let _ = reply.send(manager.connected_count());
+        }
+        RoomCommand::Status { reply } => {
+            let _ = reply.send(RoomStatus::of(room_id, manager));
+        }
+        RoomCommand::Kick {
+            filter,
+            reason,
+            reply,
+        } => {
+            let kicked = manager.kick(&filter, &reason);
+            if !kicked.is_empty() {
+                info!(?kicked, reason, "Kicked players.");
+            }
+            let _ = reply.send(kicked);
+        }
+        RoomCommand::Pause { paused } => {
+            info!(paused, "Room pause changed.");
+            manager.set_paused(paused);
+        }
+        RoomCommand::Reset { reason } => {
+            info!(players = manager.player_count(), "Resetting the room.");
+            manager.reset(&reason);
+        }
+        RoomCommand::Notice { message } => manager.notify(&message),
+        RoomCommand::Shutdown { reason, seconds } => manager.begin_shutdown(reason, seconds),
+        RoomCommand::Stop { reply } => {
+            // Dropping the clients' senders closes their sockets.
+            manager.clients.clear();
+            // Finishes the replay file before the server exits.
+            manager.recorder = None;
+            let _ = reply.send(());
+            return false;
+        }
+    }
+    true
+}
+
+/// Counts a rejected input and kicks the client once it is over the limit.
+/// Its socket closes when the snake gets removed on the next tick.
+fn reject_input(manager: &mut RoomManager, room_id: u32, client_id: Uuid, reason: InputRejection) {
+    let rejected = manager.reject_input(&client_id);
+    counter!(
+        monitoring::REJECTED_INPUTS,
+        "room" => room_id.to_string(),
+        "reason" => reason.as_str()
+    )
+    .increment(1);
+    warn!(%client_id, ?reason, rejected, "Rejected input.");
+
+    if manager
+        .config
+        .kick_after_rejected_inputs
+        .is_some_and(|limit| rejected >= limit)
+    {
+        warn!(%client_id, rejected, "Kicking client after too many rejected inputs.");
+        manager.kick_ids(&[client_id], KICK_REASON_REJECTED_INPUTS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::room_manager::{DisconnectPolicy, LagPolicy, RoomConfig, TickSchedule};
+    use multisnake_shared::controller::BotLevel;
+    use multisnake_shared::simulation::Input;
+    use multisnake_shared::{GameMode, SnakeMessage};
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn config() -> RoomConfig {
+        RoomConfig {
+            mode: GameMode::Classic,
+            tick_duration_ms: 100,
+            tick_schedule: TickSchedule::Skip,
+            reconnect_grace_ms: 1000,
+            disconnect_policy: DisconnectPolicy::Continue,
+            heartbeat_interval_ms: 2000,
+            heartbeat_timeout_ms: 6000,
+            idle_timeout_ms: 0,
+            input_queue_depth: 3,
+            max_inputs_per_tick: 4,
+            max_input_age_ticks: 100,
+            kick_after_rejected_inputs: None,
+            frame_buffer: 64,
+            lag_policy: LagPolicy::Keyframe,
+            max_players: 2,
+            bots: 0,
+            bot_level: BotLevel::Greedy,
+            password: None,
+            private: false,
+            requires_account: false,
+            ranked: false,
+            round_secs: 300,
+            invite_code: None,
+            seed: Some(7),
+            recording: None,
+        }
+    }
+
+    fn profiles() -> Profiles {
+        // Never saved, the file does not have to exist.
+        let path = std::env::temp_dir().join(format!("multisnake-test-{}.json", Uuid::new_v4()));
+        Profiles::load(path).unwrap()
+    }
+
+    /// Joins like a socket would, returning the player's id and connection.
+    fn join(manager: &mut RoomManager, profiles: &Profiles) -> Option<(Uuid, Uuid)> {
+        join_as(manager, profiles, "alice", false)
+    }
+
+    fn join_as(
+        manager: &mut RoomManager,
+        profiles: &Profiles,
+        name: &str,
+        account: bool,
+    ) -> Option<(Uuid, Uuid)> {
+        let connection_id = Uuid::new_v4();
+        let (tx, _rx) = unbounded_channel();
+        let (reply, mut reply_rx) = oneshot::channel();
+        let cmd = RoomCommand::Join {
+            session_token: None,
+            connection_id,
+            name: Some(name.to_string()),
+            account,
+            ip: None,
+            tx,
+            reply,
+        };
+        assert!(handle_command(manager, 1, profiles, cmd));
+        let joined = reply_rx.try_recv().ok()?;
+        let Message::Text(text) = joined.init else {
+            panic!("The snapshot is not text.");
+        };
+        let Ok(SnakeMessage::OnJoin { my_id, snakes, .. }) = serde_json::from_str(&text) else {
+            panic!("The snapshot is not an `OnJoin`.");
+        };
+        assert_eq!(my_id, joined.client_id);
+        assert!(snakes.contains_key(&my_id));
+        Some((joined.client_id, connection_id))
+    }
+
+    #[test]
+    fn join_adds_a_player_with_a_snake() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        let (client_id, _) = join(&mut manager, &profiles).unwrap();
+        assert_eq!(manager.player_count(), 1);
+        assert_eq!(manager.clients[&client_id].name.as_deref(), Some("alice"));
+        assert!(manager.sim.snakes.contains_key(&client_id));
+    }
+
+    #[test]
+    fn join_is_refused_when_full_or_closing() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        join(&mut manager, &profiles).unwrap();
+        join(&mut manager, &profiles).unwrap();
+        assert!(join(&mut manager, &profiles).is_none());
+
+        let mut manager = RoomManager::new(config());
+        manager.closing = true;
+        assert!(join(&mut manager, &profiles).is_none());
+        assert_eq!(manager.player_count(), 0);
+    }
+
+    #[test]
+    fn turn_is_applied_on_the_next_tick() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        let (client_id, _) = join(&mut manager, &profiles).unwrap();
+        let cmd = RoomCommand::Input {
+            client_id,
+            dir: Direction::Left,
+            tick: manager.sim.tick_count,
+        };
+        assert!(handle_command(&mut manager, 1, &profiles, cmd));
+        assert_eq!(
+            manager.pending_inputs,
+            vec![Input::Turn {
+                id: client_id,
+                dir: Direction::Left
+            }]
+        );
+
+        manager.tick();
+        assert_eq!(manager.sim.snakes[&client_id].dir, Direction::Left);
+    }
+
+    #[test]
+    fn turn_from_the_future_is_rejected() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        let (client_id, _) = join(&mut manager, &profiles).unwrap();
+        let cmd = RoomCommand::Input {
+            client_id,
+            dir: Direction::Left,
+            tick: manager.sim.tick_count + 5,
+        };
+        assert!(handle_command(&mut manager, 1, &profiles, cmd));
+        assert!(manager.pending_inputs.is_empty());
+        assert_eq!(manager.clients[&client_id].rejected_inputs, 1);
+    }
+
+    #[test]
+    fn leave_starts_the_grace_period_of_the_current_socket_only() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        let (client_id, connection_id) = join(&mut manager, &profiles).unwrap();
+
+        let stale = RoomCommand::Leave {
+            client_id,
+            connection_id: Uuid::new_v4(),
+        };
+        assert!(handle_command(&mut manager, 1, &profiles, stale));
+        assert_eq!(manager.clients[&client_id].grace_ticks, None);
+
+        let leave = RoomCommand::Leave {
+            client_id,
+            connection_id,
+        };
+        assert!(handle_command(&mut manager, 1, &profiles, leave));
+        assert_eq!(manager.clients[&client_id].grace_ticks, Some(10));
+    }
+
+    #[test]
+    fn players_in_their_grace_period_are_not_connected() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        let (client_id, connection_id) = join(&mut manager, &profiles).unwrap();
+        assert_eq!(manager.connected_count(), 1);
+        let leave = RoomCommand::Leave {
+            client_id,
+            connection_id,
+        };
+        assert!(handle_command(&mut manager, 1, &profiles, leave));
+        assert_eq!(manager.connected_count(), 0);
+        assert_eq!(manager.player_count(), 1);
+    }
+
+    #[test]
+    fn snapshot_reports_the_players() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        join(&mut manager, &profiles).unwrap();
+        let (reply, mut reply_rx) = oneshot::channel();
+        assert!(handle_command(
+            &mut manager,
+            3,
+            &profiles,
+            RoomCommand::Snapshot { reply }
+        ));
+        let update = reply_rx.try_recv().unwrap();
+        assert_eq!(update.room_id, 3);
+        assert_eq!(update.player_count, 1);
+        assert_eq!(update.max_players, 2);
+        assert!(!update.locked);
+    }
+
+    #[test]
+    fn stop_ends_the_room() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(config());
+        join(&mut manager, &profiles).unwrap();
+        let (reply, mut reply_rx) = oneshot::channel();
+        assert!(!handle_command(
+            &mut manager,
+            1,
+            &profiles,
+            RoomCommand::Stop { reply }
+        ));
+        assert!(manager.clients.is_empty());
+        assert!(reply_rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn ranked_rounds_rate_logged_in_players_only() {
+        let profiles = profiles();
+        let mut manager = RoomManager::new(RoomConfig {
+            ranked: true,
+            max_players: 3,
+            ..config()
+        });
+        let (alice, _) = join_as(&mut manager, &profiles, "alice", true).unwrap();
+        let (bob, _) = join_as(&mut manager, &profiles, "bob", false).unwrap();
+        manager.tick();
+        assert!(manager.round.is_none());
+
+        join_as(&mut manager, &profiles, "carol", true).unwrap();
+        manager.tick();
+        assert_eq!(manager.round.as_ref().map(|round| round.players()), Some(2));
+        assert!(manager.clients[&alice].rating.is_some());
+        assert!(manager.clients[&bob].rating.is_none());
+    }
+}
@@ -1,248 +1,829 @@
 use axum::extract::ws::Message;
-use std::collections::{HashMap, VecDeque};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{info, trace};
 use uuid::Uuid;
 
-use multisnake_shared::{GRID_H, GRID_W, Pos, SnakeMessage};
+use crate::profiles::GameResult;
+use crate::ratings::{MIN_ROUND_PLAYERS, Round};
+use crate::recording::{Recorder, RecordingConfig};
+use multisnake_shared::controller::{BotLevel, Bots, SnakeController};
+use multisnake_shared::replay::ReplayRecord;
+use multisnake_shared::simulation::{Event, Input, Simulation};
+use multisnake_shared::{Direction, GameMode, ScoreEntry, SnakeMessage};
+
+pub use multisnake_shared::simulation::DeathCause;
+
+const STATS_INTERVAL_TICKS: u64 = 10;
+
+/// What a snake does while its owner is disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectPolicy {
+    /// Keep moving in the last direction.
+    Continue,
+    /// Stay in place until the owner reconnects.
+    Freeze,
+}
+
+/// Why an input from a client was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputRejection {
+    /// Not valid JSON, or not a message clients may send.
+    Malformed,
+    /// Stamped with a tick the server has not reached yet.
+    FromTheFuture,
+    /// Stamped with a tick older than the client's previous input or than `max_input_age_ticks`.
+    Stale,
+    /// Over `max_inputs_per_tick` within a single tick.
+    RateLimited,
+}
+
+impl InputRejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InputRejection::Malformed => "malformed",
+            InputRejection::FromTheFuture => "from_the_future",
+            InputRejection::Stale => "stale",
+            InputRejection::RateLimited => "rate_limited",
+        }
+    }
+}
+
+/// Selects players by id, name or address. Every given field has to match,
+/// a filter without any field matches nobody. Names match in any case.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PlayerFilter {
+    pub id: Option<Uuid>,
+    pub name: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl PlayerFilter {
+    pub fn matches(&self, id: &Uuid, client: &Client) -> bool {
+        if self.id.is_none() && self.name.is_none() && self.ip.is_none() {
+            return false;
+        }
+        self.id.is_none_or(|wanted| wanted == *id)
+            && self.name.as_ref().is_none_or(|wanted| {
+                client.name.as_ref().map(|name| name.to_lowercase()) == Some(wanted.to_lowercase())
+            })
+            && self.ip.is_none_or(|wanted| client.ip == Some(wanted))
+    }
+}
+
+/// What happened during a tick, reported by the room task.
+pub struct TickSummary {
+    pub deaths: Vec<DeathCause>,
+    pub food_eaten: usize,
+    // Games of named players that ended, for their profiles.
+    pub results: Vec<GameResult>,
+    // Names by place, best first, when a ranked round ended.
+    pub standings: Option<Vec<Vec<String>>>,
+}
 
-const GHOST_TIME_MS: u32 = 8000;
-const PADDING: i32 = 15;
-const INITIAL_SNAKE_LENGTH: u32 = 5;
+/// How a room catches up after its tick task was stalled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TickSchedule {
+    /// Drop the missed ticks and stay aligned to the original schedule.
+    Skip,
+    /// Restart the schedule from the late tick, the room drifts.
+    Delay,
+    /// Run the missed ticks back to back, snakes jump several cells.
+    Burst,
+}
+
+impl From<TickSchedule> for MissedTickBehavior {
+    fn from(schedule: TickSchedule) -> Self {
+        match schedule {
+            TickSchedule::Skip => MissedTickBehavior::Skip,
+            TickSchedule::Delay => MissedTickBehavior::Delay,
+            TickSchedule::Burst => MissedTickBehavior::Burst,
+        }
+    }
+}
+
+/// What happens to a client that falls more than `frame_buffer` frames behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    /// Skip the missed frames and send a full snapshot instead.
+    Keyframe,
+    /// Close the connection, the client reconnects and gets a fresh snapshot.
+    Disconnect,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RoomConfig {
+    pub mode: GameMode,
+    pub tick_duration_ms: u32,
+    pub tick_schedule: TickSchedule,
+    pub reconnect_grace_ms: u32,
+    pub disconnect_policy: DisconnectPolicy,
+    pub heartbeat_interval_ms: u32,
+    // A connection that has not answered a ping for this long is dropped.
+    pub heartbeat_timeout_ms: u32,
+    // A player that sent no input for this long loses its snake, 0 disables it.
+    pub idle_timeout_ms: u32,
+    pub input_queue_depth: u32,
+    pub max_inputs_per_tick: u32,
+    pub max_input_age_ticks: u64,
+    // Kick clients after this many rejected inputs, `None` never kicks.
+    pub kick_after_rejected_inputs: Option<u32>,
+    // Frames buffered per room for slow clients.
+    pub frame_buffer: usize,
+    pub lag_policy: LagPolicy,
+    // Joins are refused once this many players are in the room, bots not counted.
+    pub max_players: usize,
+    // Bots are added until players and bots reach this number, 0 disables them.
+    pub bots: usize,
+    pub bot_level: BotLevel,
+    // Players have to give this password to join.
+    #[serde(skip)]
+    pub password: Option<String>,
+    // Left out of the lobby and of `/rooms`, joined by id or invite code.
+    pub private: bool,
+    // Guests are refused, players have to log in to an account.
+    pub requires_account: bool,
+    // Played in rounds that rate the logged-in players by their finishing order.
+    pub ranked: bool,
+    // Longest a ranked round lasts, the survivors are ranked by length.
+    pub round_secs: u32,
+    #[serde(skip)]
+    pub invite_code: Option<String>,
+    // Seed of the room's food and spawns, random when `None`. Kept secret,
+    // it tells where the food goes next.
+    #[serde(skip)]
+    pub seed: Option<u64>,
+    // Writes the room's games to replay files, not recorded when `None`.
+    #[serde(skip)]
+    pub recording: Option<RecordingConfig>,
+}
+
+impl RoomConfig {
+    pub fn locked(&self) -> bool {
+        self.password.is_some()
+    }
+
+    /// This configuration played as `mode`, `self` being a classic room.
+    pub fn with_mode(&self, mode: GameMode) -> RoomConfig {
+        let mut config = self.clone();
+        config.mode = mode;
+        if mode == GameMode::Fast {
+            config.tick_duration_ms = (self.tick_duration_ms / 2).max(1);
+        }
+        config
+    }
+}
 
 pub struct Client {
-    pub tx: UnboundedSender<Message>,
-    pub snake: VecDeque<Pos>,
-    pub dx: i32,
-    pub dy: i32,
-    pub next_dx: i32,
-    pub next_dy: i32,
-    pub ghost_ticks: u32,
+    // Messages for this client only, tick frames go through `RoomManager::frames_tx`.
+    // `None` for bots, which have no socket.
+    pub tx: Option<UnboundedSender<Message>>,
+    // Display name given when joining, if any.
+    pub name: Option<String>,
+    // Logged in to the account `name`, only these players are rated.
+    pub account: bool,
+    // Address of the socket currently driving this snake.
+    pub ip: Option<IpAddr>,
+    pub session_token: Uuid,
+    // Identifies the socket currently driving this snake.
+    pub connection_id: Uuid,
+    // Ticks left until a disconnected snake is removed, `None` while connected.
+    pub grace_ticks: Option<u32>,
+    pub rtt_ms: Option<u32>,
+    // Tick stamp of the newest accepted input.
+    pub last_input_tick: u64,
+    pub inputs_this_tick: u32,
+    pub rejected_inputs: u32,
+    // Frames queued for the client's socket at the last heartbeat.
+    pub backlog: usize,
+    // Frames the client missed by lagging too far behind.
+    pub lagged_frames: u64,
+    // Tick the snake joined on.
+    pub joined_tick: u64,
+    // Snakes that ran into this one.
+    pub kills: u32,
+    // Of logged-in players in ranked rooms, set by the room task.
+    pub rating: Option<u32>,
 }
 
+/// A room's game with the players and sockets around it. The snakes live in
+/// `sim`, keyed by the same ids as `clients`, and only change through inputs.
 pub struct RoomManager {
     pub clients: HashMap<Uuid, Client>,
 
-    // 2D grid flattened to 1D. Values > 1 indicate collision.
-    pub occupied: Vec<u8>,
+    pub sim: Simulation,
 
-    pub food: Pos,
+    // Seed the simulation started from, with the inputs it replays the room.
+    pub seed: u64,
 
-    // New players to be added next tick.
-    pub pending_joins: HashMap<Uuid, VecDeque<Pos>>,
+    // Inputs applied right away since the last tick, in order.
+    pub applied_inputs: Vec<Input>,
 
-    pub tick_duration_ms: u32,
+    // Inputs received since the last tick, in order.
+    pub pending_inputs: Vec<Input>,
+
+    pub bots: Bots,
+
+    // Set once the server started shutting down, no one may join anymore.
+    pub closing: bool,
+
+    // Average time between ticks as measured by the room task.
+    pub measured_tick_ms: f32,
+
+    // Each frame is serialized once and shared by all sockets.
+    pub frames_tx: broadcast::Sender<Message>,
+
+    pub config: RoomConfig,
+
+    pub created: Instant,
+
+    // Set when `config.recording` is, by whoever knows the room's id.
+    pub recorder: Option<Recorder>,
+
+    // Round being played in a ranked room.
+    pub round: Option<Round>,
 }
 
 impl RoomManager {
-    pub fn new(tick_duration_ms: u32) -> Self {
+    pub fn new(config: RoomConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(rand::random);
         Self {
             clients: HashMap::new(),
-            occupied: vec![0; (GRID_W * GRID_H) as usize],
-            food: Pos { x: 5, y: 5 },
-            pending_joins: HashMap::new(),
-            tick_duration_ms,
+            sim: Simulation::new(config.tick_duration_ms, config.input_queue_depth, seed),
+            seed,
+            applied_inputs: Vec::new(),
+            pending_inputs: Vec::new(),
+            bots: Bots::default(),
+            closing: false,
+            measured_tick_ms: config.tick_duration_ms as f32,
+            frames_tx: broadcast::channel(config.frame_buffer).0,
+            config,
+            created: Instant::now(),
+            recorder: None,
+            round: None,
         }
     }
 
-    pub fn add_client(&mut self, client_id: Uuid, tx: UnboundedSender<Message>) {
-        let initial_snake = initial_snake_segments(INITIAL_SNAKE_LENGTH);
+    pub fn add_client(
+        &mut self,
+        client_id: Uuid,
+        connection_id: Uuid,
+        name: Option<String>,
+        account: bool,
+        ip: Option<IpAddr>,
+        tx: UnboundedSender<Message>,
+    ) {
+        self.spawn_snake(client_id, connection_id, name, ip, Some(tx), None);
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.account = account;
+        }
+    }
+
+    /// Adds a snake played by the server.
+    pub fn add_bot(&mut self, controller: Box<dyn SnakeController>) -> Uuid {
+        let client_id = Uuid::new_v4();
+        let name = format!("{} bot", controller.name());
+        self.spawn_snake(
+            client_id,
+            Uuid::nil(),
+            Some(name),
+            None,
+            None,
+            Some(controller),
+        );
+        client_id
+    }
 
+    fn spawn_snake(
+        &mut self,
+        client_id: Uuid,
+        connection_id: Uuid,
+        name: Option<String>,
+        ip: Option<IpAddr>,
+        tx: Option<UnboundedSender<Message>>,
+        bot: Option<Box<dyn SnakeController>>,
+    ) {
         self.clients.insert(
             client_id,
             Client {
                 tx,
-                snake: initial_snake.clone(),
-                dx: 0,
-                dy: -1,
-                next_dx: 0,
-                next_dy: -1,
-                ghost_ticks: GHOST_TIME_MS / self.tick_duration_ms + 1,
+                name,
+                account: false,
+                ip,
+                session_token: Uuid::new_v4(),
+                connection_id,
+                grace_ticks: None,
+                rtt_ms: None,
+                last_input_tick: 0,
+                inputs_this_tick: 0,
+                rejected_inputs: 0,
+                backlog: 0,
+                lagged_frames: 0,
+                joined_tick: self.sim.tick_count,
+                kills: 0,
+                rating: None,
             },
         );
+        // Applied right away, the joining player's snapshot has to show it.
+        let join = Input::Join { id: client_id };
+        self.sim.apply(&join);
+        self.applied_inputs.push(join);
+        if let Some(controller) = bot {
+            self.bots.insert(client_id, controller);
+        }
+    }
 
-        // Add to buffer so existing players see them next tick.
-        self.pending_joins.insert(client_id, initial_snake);
+    /// Attaches a new socket to the snake owning `session_token`.
+    /// Returns the snake's id, or `None` if the token is unknown.
+    pub fn reconnect_client(
+        &mut self,
+        session_token: Uuid,
+        connection_id: Uuid,
+        ip: Option<IpAddr>,
+        tx: UnboundedSender<Message>,
+    ) -> Option<Uuid> {
+        let bots = &self.bots;
+        let (id, client) = self
+            .clients
+            .iter_mut()
+            .find(|(id, c)| c.session_token == session_token && !bots.contains(id))?;
+
+        // Replacing `tx` also ends the previous socket's handler if it is still around.
+        client.tx = Some(tx);
+        client.connection_id = connection_id;
+        client.ip = ip;
+        client.grace_ticks = None;
+        self.pending_inputs.push(Input::Freeze {
+            id: *id,
+            frozen: false,
+        });
+        Some(*id)
     }
 
-    pub fn remove_client(&mut self, client_id: &Uuid) {
-        if let Some(client) = self.clients.remove(client_id)
-            && client.ghost_ticks == 0
+    /// Starts the grace period of a snake whose socket went away.
+    /// Ignored if another socket has already taken the snake over.
+    pub fn disconnect_client(&mut self, client_id: &Uuid, connection_id: Uuid) {
+        if let Some(client) = self.clients.get_mut(client_id)
+            && client.connection_id == connection_id
         {
-            for p in &client.snake {
-                assert!(is_in_bounds(p));
-                self.occupied[idx(p)] -= 1;
-            }
+            client.grace_ticks =
+                Some(self.config.reconnect_grace_ms / self.config.tick_duration_ms);
+        }
+    }
+
+    /// Kills the snake of `client_id` on the next tick.
+    pub fn evict_client(&mut self, client_id: &Uuid, cause: DeathCause) {
+        self.pending_inputs.push(Input::Evict {
+            id: *client_id,
+            cause,
+        });
+    }
+
+    /// Tells the matching players why they are kicked and removes their snakes
+    /// on the next tick, which also closes their sockets. Returns their ids.
+    pub fn kick(&mut self, filter: &PlayerFilter, reason: &str) -> Vec<Uuid> {
+        let kicked: Vec<Uuid> = self
+            .clients
+            .iter()
+            .filter(|(id, client)| filter.matches(id, client))
+            .map(|(id, _)| *id)
+            .collect();
+        self.kick_ids(&kicked, reason);
+        kicked
+    }
+
+    pub fn kick_ids(&mut self, client_ids: &[Uuid], reason: &str) {
+        for id in client_ids {
+            self.send_to(
+                id,
+                &SnakeMessage::Kicked {
+                    reason: reason.to_string(),
+                },
+            );
+            self.evict_client(id, DeathCause::Kicked);
+        }
+    }
+
+    /// Kicks everyone, the room starts over with fresh food and no longer paused.
+    /// The round being played is called off, no one is rated.
+    pub fn reset(&mut self, reason: &str) {
+        self.round = None;
+        let everyone: Vec<Uuid> = self.clients.keys().copied().collect();
+        self.kick_ids(&everyone, reason);
+        self.pending_inputs.push(Input::Pause { paused: false });
+        self.pending_inputs.push(Input::RespawnFood);
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.pending_inputs.push(Input::Pause { paused });
+        let message = if paused {
+            "The room was paused by an admin."
+        } else {
+            "The room was resumed."
+        };
+        self.notify(message);
+    }
+
+    /// Shows `message` to everyone in the room.
+    pub fn notify(&self, message: &str) {
+        if let Some(msg) = encode(&SnakeMessage::ServerNotice {
+            message: message.to_string(),
+        }) {
+            self.broadcast(msg);
+        }
+    }
+
+    /// Shows `rating` next to the snakes of `name`.
+    pub fn set_rating(&mut self, name: &str, rating: u32) {
+        let bots = &self.bots;
+        for (_, client) in self
+            .clients
+            .iter_mut()
+            .filter(|(id, client)| client.name.as_deref() == Some(name) && !bots.contains(id))
+        {
+            client.rating = Some(rating);
+        }
+    }
+
+    /// Average rating of the rated players, for matchmaking.
+    pub fn average_rating(&self) -> Option<u32> {
+        let ratings: Vec<u32> = self.clients.values().filter_map(|c| c.rating).collect();
+        if ratings.is_empty() {
+            return None;
+        }
+        Some(ratings.iter().sum::<u32>() / ratings.len() as u32)
+    }
+
+    pub fn record_rtt(&mut self, client_id: &Uuid, rtt_ms: u32) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.rtt_ms = Some(rtt_ms);
+        }
+    }
+
+    pub fn begin_shutdown(&mut self, reason: String, seconds: u32) {
+        self.closing = true;
+        if let Some(msg) = encode(&SnakeMessage::ServerShutdown { reason, seconds }) {
+            self.broadcast(msg);
+        }
+    }
+
+    pub fn record_backlog(&mut self, client_id: &Uuid, backlog: usize) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.backlog = backlog;
         }
     }
 
-    pub fn queue_move(&mut self, client_id: &Uuid, dx: i32, dy: i32) {
+    pub fn record_lag(&mut self, client_id: &Uuid, skipped: u64) {
         if let Some(client) = self.clients.get_mut(client_id) {
-            // Prevent 180 degree turns
-            if dx != -client.dx || dy != -client.dy {
-                client.next_dx = dx;
-                client.next_dy = dy;
+            client.lagged_frames += skipped;
+        }
+    }
+
+    /// Validates the tick stamp and rate of an input and queues the turn.
+    /// Reversals and turns over the queue depth are dropped without being rejected.
+    pub fn queue_move(
+        &mut self,
+        client_id: &Uuid,
+        dir: Direction,
+        tick: u64,
+    ) -> Result<(), InputRejection> {
+        let Some(client) = self.clients.get_mut(client_id) else {
+            return Ok(());
+        };
+        let tick_count = self.sim.tick_count;
+
+        if tick > tick_count {
+            return Err(InputRejection::FromTheFuture);
+        }
+        if tick < client.last_input_tick || tick_count - tick > self.config.max_input_age_ticks {
+            return Err(InputRejection::Stale);
+        }
+        if client.inputs_this_tick >= self.config.max_inputs_per_tick {
+            return Err(InputRejection::RateLimited);
+        }
+
+        client.last_input_tick = tick;
+        client.inputs_this_tick += 1;
+
+        // The simulation drops 180 degree turns against the previously queued direction.
+        self.pending_inputs.push(Input::Turn {
+            id: *client_id,
+            dir,
+        });
+        Ok(())
+    }
+
+    /// Counts a rejected input, returning how many the client has had so far.
+    pub fn reject_input(&mut self, client_id: &Uuid) -> u32 {
+        match self.clients.get_mut(client_id) {
+            Some(client) => {
+                client.rejected_inputs += 1;
+                client.rejected_inputs
             }
+            None => 0,
         }
     }
 
     pub fn new_init_message(&self, my_id: Uuid) -> SnakeMessage {
         SnakeMessage::OnJoin {
             my_id,
-            snakes: self
-                .clients
-                .iter()
-                .map(|(k, v)| (*k, v.snake.clone()))
-                .collect(),
-            tick_duration_ms: self.tick_duration_ms,
-            food: self.food,
+            session_token: self.clients[&my_id].session_token,
+            snakes: self.sim.bodies(),
+            tick_duration_ms: self.config.tick_duration_ms,
+            input_queue_depth: self.config.input_queue_depth,
+            food: self.sim.food,
+            tick: self.sim.tick_count,
         }
     }
 
-    /// The Server tick
-    pub fn tick(&mut self) {
-        let mut moves_to_broadcast = HashMap::new();
-        let mut dead_clients = Vec::new();
-        let mut eaters = Vec::new();
-        let mut client_ghosts = Vec::new();
+    /// Players in the room, bots left out.
+    pub fn player_count(&self) -> usize {
+        self.clients.len() - self.bots.len()
+    }
 
-        // Calculate moves and wall collisions.
-        for (id, client) in self.clients.iter_mut() {
-            if client.ghost_ticks > 0 {
-                client.ghost_ticks -= 1;
-                if client.ghost_ticks == 0 {
-                    // Mark occupied grid when ghost mode ends.
-                    for p in &client.snake {
-                        self.occupied[idx(p)] += 1;
-                    }
-                } else {
-                    client_ghosts.push(*id);
-                }
+    /// Players whose socket is still there, those in their reconnect grace left out.
+    pub fn connected_count(&self) -> usize {
+        self.clients
+            .values()
+            .filter(|client| client.tx.is_some() && client.grace_ticks.is_none())
+            .count()
+    }
+
+    pub fn bot_count(&self) -> usize {
+        self.bots.len()
+    }
+
+    /// Adds or removes bots so that players and bots make `config.bots` snakes.
+    /// Bots leave on the tick a player takes their place.
+    fn balance_bots(&mut self) {
+        let wanted = self.config.bots.saturating_sub(self.player_count());
+        let bots: Vec<Uuid> = self.bots.ids().copied().collect();
+
+        if bots.len() > wanted {
+            for id in &bots[wanted..] {
+                self.evict_client(id, DeathCause::Replaced);
             }
+        } else if !self.closing {
+            for _ in bots.len()..wanted {
+                self.add_bot(self.config.bot_level.controller());
+            }
+        }
+    }
 
-            let (dx, dy, current_head) = {
-                (
-                    client.next_dx,
-                    client.next_dy,
-                    *client.snake.front().unwrap(),
-                )
-            };
+    /// The Server tick
+    pub fn tick(&mut self) -> TickSummary {
+        self.balance_bots();
 
-            let next_x = current_head.x + dx;
-            let next_y = current_head.y + dy;
-            let new_head = Pos {
-                x: next_x,
-                y: next_y,
-            };
+        for (id, client) in self.clients.iter_mut() {
+            client.inputs_this_tick = 0;
 
-            // Wall check.
-            if !is_in_bounds(&new_head) {
-                dead_clients.push(*id);
+            // Grace periods do not run while paused.
+            if self.sim.paused {
                 continue;
             }
-
-            // Apply move logic (grow or move).
-            let old_tail = {
-                client.dx = dx;
-                client.dy = dy;
-
-                let ate = new_head == self.food && client.ghost_ticks == 0;
-                let tail = if ate {
-                    None
-                } else {
-                    Some(*client.snake.back().unwrap())
-                };
-
-                client.snake.push_front(new_head);
-                if !ate {
-                    client.snake.pop_back();
-                } else {
-                    eaters.push(*id);
+            if let Some(grace_ticks) = client.grace_ticks.as_mut() {
+                if *grace_ticks == 0 {
+                    // Owner did not come back in time.
+                    self.pending_inputs.push(Input::Evict {
+                        id: *id,
+                        cause: DeathCause::Abandoned,
+                    });
+                    continue;
                 }
-                tail
-            };
-
-            if client.ghost_ticks == 0 {
-                // Update `occupied` grid.
-                self.occupied[idx(&new_head)] += 1;
-                if let Some(old_tail) = old_tail {
-                    self.occupied[idx(&old_tail)] -= 1;
+                *grace_ticks -= 1;
+                if self.config.disconnect_policy == DisconnectPolicy::Freeze {
+                    self.pending_inputs.push(Input::Freeze {
+                        id: *id,
+                        frozen: true,
+                    });
                 }
             }
         }
 
-        for _ in 0..eaters.len() {
-            self.respawn_food();
+        let mut inputs = std::mem::take(&mut self.pending_inputs);
+        inputs.extend(self.bots.steer(&self.sim));
+        // What the simulation went through since the last tick: from the seed,
+        // these inputs reproduce the room exactly.
+        let applied = std::mem::take(&mut self.applied_inputs);
+        if !applied.is_empty() || !inputs.is_empty() {
+            trace!(tick = self.sim.tick_count + 1, ?applied, ?inputs, "Inputs.");
         }
+        let events = self.sim.step(&inputs);
 
-        // Snake-to-snake collision check.
-        for (id, client) in &self.clients {
-            if dead_clients.contains(id) {
-                continue;
-            } // TODO opt?
+        // Broadcast TickUpdate.
+        let update = self.sim.tick_update(&events);
+        if let Some(msg) = encode(&update) {
+            self.broadcast(msg);
+        }
 
-            let head = client.snake.front().unwrap();
+        // Kills first, the killer may die on the same tick.
+        for event in &events {
+            if let Event::Died {
+                killer: Some(killer),
+                ..
+            } = event
+                && let Some(client) = self.clients.get_mut(killer)
+            {
+                client.kills += 1;
+            }
+        }
 
-            // > 1 means that there is a collision, we ignore ghost snakes.
-            if client.ghost_ticks == 0 && self.occupied[idx(head)] > 1 {
-                dead_clients.push(*id);
-            } else {
-                moves_to_broadcast.insert(*id, (client.dx, client.dy));
+        // Remove clients which died.
+        let mut deaths = Vec::new();
+        let mut food_eaten = 0;
+        let mut results = Vec::new();
+        let mut died = Vec::new();
+        for event in &events {
+            match event {
+                Event::Died {
+                    id, cause, length, ..
+                } => {
+                    info!(client_id = %id, ?cause, length, "Snake died.");
+                    died.push(*id);
+                    let bot = self.bots.remove(id).is_some();
+                    if let Some(client) = self.clients.remove(id)
+                        && !bot
+                        && let Some(name) = client.name
+                    {
+                        results.push(GameResult {
+                            name,
+                            length: *length,
+                            survival_ms: (self.sim.tick_count - client.joined_tick)
+                                * self.config.tick_duration_ms as u64,
+                            kills: client.kills,
+                        });
+                    }
+                    deaths.push(*cause);
+                }
+                Event::Ate { .. } => food_eaten += 1,
+                _ => {}
             }
         }
 
-        // Broadcast TickUpdate.
-        let update = SnakeMessage::TickUpdate {
-            moves: moves_to_broadcast,
-            food: self.food,
-            deaths: dead_clients.clone(),
-            eaters,
-            new_snakes: self.pending_joins.clone(),
-            ghosts: client_ghosts,
+        let standings = if self.config.ranked {
+            self.play_round(&died)
+        } else {
+            None
         };
 
-        self.pending_joins.clear();
+        if self.recorder.is_some() {
+            let mut recorded = applied;
+            recorded.extend(inputs);
+            self.record(recorded, update, &events);
+        }
 
-        if let Ok(json) = serde_json::to_string(&update) {
-            self.broadcast(json);
+        if self.sim.tick_count.is_multiple_of(STATS_INTERVAL_TICKS) {
+            let scoreboard = SnakeMessage::Scoreboard {
+                entries: self
+                    .sim
+                    .snakes
+                    .iter()
+                    .map(|(id, snake)| ScoreEntry {
+                        id: *id,
+                        length: snake.body.len(),
+                        rtt_ms: self.clients.get(id).and_then(|c| c.rtt_ms),
+                        bot: self.bots.contains(id),
+                        rating: self.clients.get(id).and_then(|c| c.rating),
+                    })
+                    .collect(),
+            };
+            if let Some(msg) = encode(&scoreboard) {
+                self.broadcast(msg);
+            }
+
+            let tick_rate = SnakeMessage::TickRate {
+                measured_tick_ms: self.measured_tick_ms,
+            };
+            if let Some(msg) = encode(&tick_rate) {
+                self.broadcast(msg);
+            }
         }
 
-        // Remove clients which died.
-        for id in &dead_clients {
-            self.remove_client(id);
+        TickSummary {
+            deaths,
+            food_eaten,
+            results,
+            standings,
+        }
+    }
+
+    /// Takes the snakes that `died` out of the round, returning its standings
+    /// once it is over. Starts a round when none is played and enough logged-in
+    /// players are in.
+    fn play_round(&mut self, died: &[Uuid]) -> Option<Vec<Vec<String>>> {
+        let tick = self.sim.tick_count;
+        if let Some(round) = self.round.as_mut() {
+            round.eliminate(died);
+            let max_ticks =
+                self.config.round_secs as u64 * 1000 / self.config.tick_duration_ms as u64;
+            if !round.is_over(tick, max_ticks) {
+                return None;
+            }
+            let round = self.round.take()?;
+            let players = round.players();
+            let standings =
+                round.standings(|id| self.sim.snakes.get(id).map_or(0, |snake| snake.body.len()));
+            let winners = standings.first().map(|names| names.join(", "));
+            info!(players, ?winners, "Ranked round over.");
+            if let Some(winners) = winners {
+                self.notify(&format!("Round over, won by {}.", winners));
+            }
+            return Some(standings);
+        }
+
+        // Guests could play under anyone's unregistered name, only accounts are rated.
+        // A name in the round twice would be rated twice, only one of its snakes plays.
+        let mut names = HashSet::new();
+        let players: HashMap<Uuid, String> = self
+            .clients
+            .iter()
+            .filter(|(id, client)| client.account && !self.bots.contains(id))
+            .filter_map(|(id, client)| Some((*id, client.name.clone()?)))
+            .filter(|(_, name)| names.insert(name.clone()))
+            .collect();
+        if players.len() >= MIN_ROUND_PLAYERS {
+            info!(players = players.len(), "Ranked round started.");
+            self.notify(&format!(
+                "A ranked round started with {} players.",
+                players.len()
+            ));
+            self.round = Some(Round::start(tick, players));
         }
+        None
     }
 
-    fn respawn_food(&mut self) {
-        self.food = Pos {
-            x: rand::random::<u16>() as i32 % (GRID_W - 2 * PADDING) + PADDING,
-            y: rand::random::<u16>() as i32 % (GRID_H - 2 * PADDING) + PADDING,
+    /// Appends the tick to the room's replay.
+    fn record(&mut self, inputs: Vec<Input>, update: SnakeMessage, events: &[Event]) {
+        let Some(mut recorder) = self.recorder.take() else {
+            return;
         };
+        let names = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Spawned { id, .. } => Some((*id, self.clients.get(id)?.name.clone()?)),
+                _ => None,
+            })
+            .collect();
+        let record = ReplayRecord::Tick {
+            inputs,
+            update,
+            names,
+        };
+        recorder.record(
+            self.sim.tick_count,
+            record,
+            self.sim.snakes.is_empty(),
+            || self.keyframe(),
+        );
+        self.recorder = Some(recorder);
     }
 
-    fn broadcast(&self, txt: String) {
-        let msg = Message::Text(txt.into());
-        for client in self.clients.values() {
-            let _ = client.tx.send(msg.clone());
+    /// The room as it is, for replays to start or seek from.
+    fn keyframe(&self) -> ReplayRecord {
+        ReplayRecord::Keyframe {
+            tick: self.sim.tick_count,
+            snakes: self.sim.bodies(),
+            food: self.sim.food,
+            ghosts: self
+                .sim
+                .snakes
+                .iter()
+                .filter(|(_, snake)| snake.ghost_ticks > 0)
+                .map(|(id, _)| *id)
+                .collect(),
+            names: self
+                .clients
+                .iter()
+                .filter_map(|(id, client)| Some((*id, client.name.clone()?)))
+                .collect(),
         }
     }
-}
 
-fn initial_snake_segments(length: u32) -> VecDeque<Pos> {
-    let start_x = rand::random::<u16>() as i32 % (GRID_W - 2 * PADDING) + PADDING;
-    let start_y = rand::random::<u16>() as i32 % (GRID_H - 2 * PADDING) + PADDING;
-
-    (0..length)
-        .map(|i| Pos {
-            x: start_x,
-            y: start_y + i as i32,
-        })
-        .collect()
-}
+    fn send_to(&self, client_id: &Uuid, msg: &SnakeMessage) {
+        if let Some(client) = self.clients.get(client_id)
+            && let Some(tx) = &client.tx
+            && let Some(msg) = encode(msg)
+        {
+            // Fails only when the socket is already gone.
+            let _ = tx.send(msg);
+        }
+    }
 
-fn idx(p: &Pos) -> usize {
-    p.y as usize * GRID_W as usize + p.x as usize
+    fn broadcast(&self, msg: Message) {
+        // Fails only when nobody is subscribed.
+        let _ = self.frames_tx.send(msg);
+    }
 }
 
-fn is_in_bounds(p: &Pos) -> bool {
-    p.x >= 0 && p.x < GRID_W && p.y >= 0 && p.y < GRID_H
+pub fn encode(msg: &SnakeMessage) -> Option<Message> {
+    serde_json::to_string(msg)
+        .ok()
+        .map(|json| Message::Text(json.into()))
 }
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard, broadcast};
+use tokio::time::{self, Instant};
+use tracing::info;
+
+use crate::accounts::Accounts;
+use crate::admin::BanList;
+use crate::profiles::Profiles;
+use crate::recording::Recorder;
+use crate::room_actor::{self, RoomHandle};
+use crate::room_manager::{RoomConfig, RoomManager};
+use crate::socket_handlers::RoomContext;
+use multisnake_shared::{GameMode, LobbyMessage};
+
+// Rooms opened by quick play are closed once they stayed empty this long.
+const IDLE_ROOM_GRACE: Duration = Duration::from_secs(60);
+const IDLE_ROOM_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Every room of the server, by id. Rooms are opened at startup and by quick play.
+pub struct Rooms {
+    rooms: RwLock<BTreeMap<u32, Arc<RoomContext>>>,
+    // Ids are never reused, links and replays of a closed room must not lead to another one.
+    next_id: AtomicU32,
+    // Configuration of classic rooms, other modes are derived from it.
+    template: RoomConfig,
+    max_rooms: usize,
+    lobby_tx: broadcast::Sender<LobbyMessage>,
+    shutting_down: Arc<AtomicBool>,
+    bans: Arc<BanList>,
+    profiles: Arc<Profiles>,
+    accounts: Arc<Accounts>,
+    // Held while quick play picks or opens a room and while idle rooms close,
+    // so that concurrent requests do not each open a room.
+    quick_play: Mutex<()>,
+}
+
+impl Rooms {
+    pub fn new(
+        template: RoomConfig,
+        max_rooms: usize,
+        lobby_tx: broadcast::Sender<LobbyMessage>,
+        shutting_down: Arc<AtomicBool>,
+        bans: Arc<BanList>,
+        profiles: Arc<Profiles>,
+        accounts: Arc<Accounts>,
+    ) -> Self {
+        Self {
+            rooms: RwLock::new(BTreeMap::new()),
+            next_id: AtomicU32::new(1),
+            template,
+            max_rooms,
+            lobby_tx,
+            shutting_down,
+            bans,
+            profiles,
+            accounts,
+            quick_play: Mutex::new(()),
+        }
+    }
+
+    pub fn template(&self) -> &RoomConfig {
+        &self.template
+    }
+
+    pub fn profiles(&self) -> &Profiles {
+        &self.profiles
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub async fn lock_quick_play(&self) -> MutexGuard<'_, ()> {
+        self.quick_play.lock().await
+    }
+
+    /// Spawns a room with a new id. Returns `None` once `max_rooms` rooms are open.
+    pub fn open(&self, config: RoomConfig) -> Option<Arc<RoomContext>> {
+        self.spawn(config, false)
+    }
+
+    fn spawn(&self, config: RoomConfig, on_demand: bool) -> Option<Arc<RoomContext>> {
+        let mut rooms = self.rooms.write().unwrap_or_else(|err| err.into_inner());
+        if rooms.len() >= self.max_rooms {
+            return None;
+        }
+        let room_id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        let mut manager = RoomManager::new(config.clone());
+        if let Some(recording) = &config.recording {
+            manager.recorder = Some(Recorder::new(
+                recording.clone(),
+                room_id,
+                config.mode,
+                config.tick_duration_ms,
+                manager.seed,
+            ));
+        }
+        let room = room_actor::spawn_room(
+            room_id,
+            manager,
+            self.lobby_tx.clone(),
+            self.profiles.clone(),
+        );
+        let ctx = Arc::new(RoomContext {
+            room_id,
+            room,
+            config,
+            on_demand,
+            shutting_down: self.shutting_down.clone(),
+            bans: self.bans.clone(),
+            accounts: self.accounts.clone(),
+        });
+        rooms.insert(room_id, ctx.clone());
+        Some(ctx)
+    }
+
+    /// Opens a room of `mode` with the template configuration, closed by
+    /// `close_idle_periodically` once left empty.
+    pub fn open_mode(&self, mode: GameMode) -> Option<Arc<RoomContext>> {
+        self.spawn(self.template.with_mode(mode), true)
+    }
+
+    /// Closes the rooms opened by quick play once they stayed empty for
+    /// `IDLE_ROOM_GRACE`, freeing their slot of `max_rooms`.
+    pub async fn close_idle_periodically(self: Arc<Self>) {
+        let mut interval = time::interval(IDLE_ROOM_CHECK_INTERVAL);
+        let mut empty_since: HashMap<u32, Instant> = HashMap::new();
+        loop {
+            interval.tick().await;
+            let _quick_play = self.lock_quick_play().await;
+            // The shutdown stops every room itself.
+            if self.shutting_down() {
+                return;
+            }
+            for ctx in self.all().into_iter().filter(|ctx| ctx.on_demand) {
+                let empty = ctx
+                    .room
+                    .snapshot()
+                    .await
+                    .is_none_or(|update| update.player_count == 0);
+                if !empty {
+                    empty_since.remove(&ctx.room_id);
+                    continue;
+                }
+                let since = *empty_since.entry(ctx.room_id).or_insert_with(Instant::now);
+                if since.elapsed() < IDLE_ROOM_GRACE {
+                    continue;
+                }
+
+                empty_since.remove(&ctx.room_id);
+                self.rooms
+                    .write()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .remove(&ctx.room_id);
+                ctx.room.stop().await;
+                info!(room_id = ctx.room_id, "Closed an empty quick play room.");
+                let _ = self.lobby_tx.send(LobbyMessage::RoomClosed {
+                    room_id: ctx.room_id,
+                });
+            }
+        }
+    }
+
+    pub fn get(&self, room_id: u32) -> Option<Arc<RoomContext>> {
+        let rooms = self.rooms.read().unwrap_or_else(|err| err.into_inner());
+        rooms.get(&room_id).cloned()
+    }
+
+    /// The private room behind an invite code, which is case insensitive.
+    pub fn by_invite_code(&self, code: &str) -> Option<Arc<RoomContext>> {
+        let code = code.to_uppercase();
+        let rooms = self.rooms.read().unwrap_or_else(|err| err.into_inner());
+        rooms
+            .values()
+            .find(|ctx| ctx.config.invite_code.as_deref() == Some(code.as_str()))
+            .cloned()
+    }
+
+    pub fn all(&self) -> Vec<Arc<RoomContext>> {
+        let rooms = self.rooms.read().unwrap_or_else(|err| err.into_inner());
+        rooms.values().cloned().collect()
+    }
+
+    pub fn handles(&self) -> Vec<RoomHandle> {
+        let rooms = self.rooms.read().unwrap_or_else(|err| err.into_inner());
+        rooms.values().map(|ctx| ctx.room.clone()).collect()
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{self, Instant};
+use tracing::info;
+
+use crate::room_actor::{RoomCommand, RoomHandle};
+use multisnake_shared::LobbyMessage;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const SHUTDOWN_REASON: &str = "The server is shutting down.";
+
+/// Resolves with the name of the first termination signal received.
+pub async fn wait_for_signal() -> &'static str {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => "SIGINT",
+        _ = terminate => "SIGTERM",
+    }
+}
+
+/// Tells everyone the server is going down, lets running games play out
+/// until no player is connected or `timeout` passes, then stops the rooms.
+/// Snakes waiting for their player to reconnect do not hold the shutdown up.
+pub async fn drain(
+    rooms: &[RoomHandle],
+    lobby_tx: &broadcast::Sender<LobbyMessage>,
+    timeout: Duration,
+) {
+    let seconds = timeout.as_secs() as u32;
+
+    let _ = lobby_tx.send(LobbyMessage::ServerShutdown {
+        reason: SHUTDOWN_REASON.to_string(),
+        seconds,
+    });
+    for room in rooms {
+        room.send(RoomCommand::Shutdown {
+            reason: SHUTDOWN_REASON.to_string(),
+            seconds,
+        })
+        .await;
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut players = 0;
+    while Instant::now() < deadline {
+        players = 0;
+        for room in rooms {
+            players += room.connected().await.unwrap_or(0);
+        }
+        if players == 0 {
+            break;
+        }
+        time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    if players > 0 {
+        info!(
+            players,
+            "Shutdown timeout reached, stopping the remaining games."
+        );
+    }
+
+    for room in rooms {
+        room.stop().await;
+    }
+}
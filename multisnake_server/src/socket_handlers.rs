@@ -1,69 +1,319 @@
 use axum::{
     extract::{
-        State,
+        ConnectInfo, Path, Query, State,
         ws::{Message, WebSocket, WebSocketUpgrade},
     },
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use futures_util::{SinkExt, stream::StreamExt};
+use futures_util::{
+    SinkExt,
+    stream::{SplitSink, StreamExt},
+};
+use metrics::{Counter, counter};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::{Mutex, broadcast};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio::time;
+use tracing::{Instrument, Span, debug, field, info, info_span, warn};
 use uuid::Uuid;
 
-use crate::room_manager::RoomManager;
-use multisnake_shared::{LobbyUpdate, SnakeMessage};
+use crate::accounts::{self, Accounts};
+use crate::admin::BanList;
+use crate::matchmaking;
+use crate::monitoring;
+use crate::profiles::{self, Profiles};
+use crate::room_actor::{Joined, RoomCommand, RoomHandle};
+use crate::room_manager::{DeathCause, InputRejection, LagPolicy, RoomConfig};
+use crate::rooms::Rooms;
+use crate::status;
+use multisnake_shared::{LobbyMessage, LobbyRequest, SnakeMessage};
+
+const MAX_NAME_LEN: usize = 16;
+// Pings awaiting their pong, a client slower than the heartbeat still matches them.
+const MAX_PENDING_PINGS: usize = 8;
 
 pub struct RoomContext {
-    pub room_manager: Arc<Mutex<RoomManager>>,
-    pub lobby_tx: broadcast::Sender<LobbyUpdate>,
     pub room_id: u32,
+    pub room: RoomHandle,
+    pub config: RoomConfig,
+    // Opened by quick play, closed once left empty.
+    pub on_demand: bool,
+    pub shutting_down: Arc<AtomicBool>,
+    pub bans: Arc<BanList>,
+    pub accounts: Arc<Accounts>,
 }
 
 pub struct TuiContext {
-    pub lobby_tx: broadcast::Sender<LobbyUpdate>,
-    pub snapshot_req_tx: mpsc::UnboundedSender<mpsc::UnboundedSender<Vec<LobbyUpdate>>>,
+    pub lobby_tx: broadcast::Sender<LobbyMessage>,
+    pub shutting_down: Arc<AtomicBool>,
+    pub rooms: Arc<Rooms>,
+    pub profiles: Arc<Profiles>,
+}
+
+#[derive(Deserialize)]
+pub struct JoinParams {
+    /// Session token from a previous `OnJoin`, used to take back a snake.
+    pub token: Option<Uuid>,
+    /// Display name, shown in the status routes. Ignored when logged in.
+    pub name: Option<String>,
+    /// Token from `/accounts/login`, plays under the account's name.
+    pub auth: Option<Uuid>,
+    /// Required by rooms with a password.
+    pub password: Option<String>,
+}
+
+/// Drops control characters and caps the length, an empty name counts as none.
+pub fn sanitize_name(name: &str) -> Option<String> {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    (!name.is_empty()).then_some(name)
 }
 
 pub async fn in_room_handler(
     ws: WebSocketUpgrade,
-    State(ctx): State<Arc<RoomContext>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_in_room_connection(socket, ctx))
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(room_id): Path<u32>,
+    Query(params): Query<JoinParams>,
+    State(rooms): State<Arc<Rooms>>,
+) -> Response {
+    match rooms.get(room_id) {
+        Some(ctx) => join_room(ws, addr, params, ctx).await,
+        None => (StatusCode::NOT_FOUND, "Unknown room.").into_response(),
+    }
+}
+
+/// Joins the private room behind an invite code.
+pub async fn invite_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(code): Path<String>,
+    Query(params): Query<JoinParams>,
+    State(rooms): State<Arc<Rooms>>,
+) -> Response {
+    match rooms.by_invite_code(&code) {
+        Some(ctx) => join_room(ws, addr, params, ctx).await,
+        None => (StatusCode::NOT_FOUND, "Unknown invite code.").into_response(),
+    }
+}
+
+async fn join_room(
+    ws: WebSocketUpgrade,
+    addr: SocketAddr,
+    mut params: JoinParams,
+    ctx: Arc<RoomContext>,
+) -> Response {
+    if ctx.shutting_down.load(Ordering::SeqCst) {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    if let Some(password) = &ctx.config.password
+        && !params
+            .password
+            .as_ref()
+            .is_some_and(|given| accounts::same_bytes(given.as_bytes(), password.as_bytes()))
+    {
+        info!(%addr, room_id = ctx.room_id, "Refused a wrong password.");
+        return (StatusCode::UNAUTHORIZED, "Wrong password.").into_response();
+    }
+    params.name = params.name.as_deref().and_then(sanitize_name);
+    match params.auth {
+        Some(token) => match ctx.accounts.authenticate(token) {
+            Some(name) => params.name = Some(name),
+            None => {
+                return (StatusCode::UNAUTHORIZED, "Unknown login, log in again.").into_response();
+            }
+        },
+        None if ctx.config.requires_account => {
+            return (StatusCode::UNAUTHORIZED, "This room needs an account.").into_response();
+        }
+        None => {
+            if let Some(name) = &params.name
+                && ctx.accounts.is_registered(name)
+            {
+                info!(%addr, name, "Refused a guest using a registered name.");
+                return (
+                    StatusCode::FORBIDDEN,
+                    "This name is registered, log in to use it.",
+                )
+                    .into_response();
+            }
+        }
+    }
+    if let Some(reason) = ctx.bans.check(Some(addr.ip()), params.name.as_deref()) {
+        info!(%addr, name = ?params.name, "Refused a banned player.");
+        return (StatusCode::FORBIDDEN, reason).into_response();
+    }
+    // Players taking back their snake do not need a free spot. The room checks
+    // again when joining, this only gives the client a reason.
+    if params.token.is_none()
+        && let Some(update) = ctx.room.snapshot().await
+        && update.player_count >= update.max_players
+    {
+        return (StatusCode::CONFLICT, "The room is full.").into_response();
+    }
+    let connection_id = Uuid::new_v4();
+    // `client_id` is filled in once the room has accepted the connection.
+    let span = info_span!(
+        "connection",
+        room_id = ctx.room_id,
+        %connection_id,
+        %addr,
+        client_id = field::Empty,
+    );
+    ws.on_upgrade(move |socket| {
+        let room = ctx.room_id.to_string();
+        async move {
+            counter!(monitoring::WS_CONNECTS, "room" => room.clone()).increment(1);
+            handle_in_room_connection(socket, params, ctx, connection_id, addr).await;
+            counter!(monitoring::WS_DISCONNECTS, "room" => room).increment(1);
+        }
+        .instrument(span)
+    })
 }
 
 pub async fn in_tui_handler(
     ws: WebSocketUpgrade,
     State(tui_ctx): State<Arc<TuiContext>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_in_tui_connection(socket, tui_ctx))
+) -> Response {
+    if tui_ctx.shutting_down.load(Ordering::SeqCst) {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+    ws.on_upgrade(move |socket| {
+        async move {
+            counter!(monitoring::WS_CONNECTS, "room" => "lobby").increment(1);
+            handle_in_tui_connection(socket, tui_ctx).await;
+            counter!(monitoring::WS_DISCONNECTS, "room" => "lobby").increment(1);
+        }
+        .instrument(info_span!("lobby"))
+    })
 }
 
-async fn handle_in_room_connection(socket: WebSocket, ctx: Arc<RoomContext>) {
-    let client_id = Uuid::new_v4();
-    let (mut ws_tx, mut ws_rx) = socket.split();
+async fn handle_in_room_connection(
+    socket: WebSocket,
+    params: JoinParams,
+    ctx: Arc<RoomContext>,
+    connection_id: Uuid,
+    addr: SocketAddr,
+) {
+    let room = ctx.room_id.to_string();
+    let (ws_tx, mut ws_rx) = socket.split();
+    let mut ws_tx = CountingSink::new(ws_tx, &room);
     let (tx, mut rx) = mpsc::unbounded_channel();
 
-    {
-        let mut room_guard = ctx.room_manager.lock().await;
-        room_guard.add_client(client_id, tx.clone());
-
-        let init_msg = room_guard.new_init_message(client_id);
-        if let Ok(json) = serde_json::to_string(&init_msg) {
-            let _ = tx.send(Message::Text(json.into()));
-        }
+    let Some(Joined {
+        client_id,
+        init,
+        mut frames,
+    }) = ctx
+        .room
+        .join(
+            params.token,
+            connection_id,
+            params.name,
+            // `join_room` turned unknown logins away.
+            params.auth.is_some(),
+            Some(addr.ip()),
+            tx,
+        )
+        .await
+    else {
+        debug!("The room turned the connection away.");
+        return;
+    };
+    Span::current().record("client_id", field::display(client_id));
+    let leave = RoomCommand::Leave {
+        client_id,
+        connection_id,
+    };
 
-        let player_count = room_guard.clients.len();
-        let _ = ctx.lobby_tx.send(LobbyUpdate {
-            room_id: ctx.room_id,
-            player_count,
-        });
+    if let Err(err) = ws_tx.send(init).await {
+        debug!("Could not send the snapshot: {}", err);
+        ctx.room.send(leave).await;
+        return;
     }
 
+    let heartbeat_timeout = Duration::from_millis(ctx.config.heartbeat_timeout_ms as u64);
+    let idle_timeout = Duration::from_millis(ctx.config.idle_timeout_ms as u64);
+    let mut heartbeat = time::interval(Duration::from_millis(
+        ctx.config.heartbeat_interval_ms as u64,
+    ));
+    let mut ping_seq = 0;
+    // Oldest first.
+    let mut pending_pings: VecDeque<(u32, Instant)> = VecDeque::new();
+    let mut last_pong = Instant::now();
+    let mut last_input = Instant::now();
+    let mut evicted = false;
+
     loop {
         tokio::select! {
-            // Outbound: From Room manager -> WebSocket
-            Some(msg) = rx.recv() => {
+            // Heartbeat: ping the client, drop it if it stopped answering.
+            _ = heartbeat.tick() => {
+                if last_pong.elapsed() > heartbeat_timeout {
+                    info!("Client missed its heartbeats.");
+                    break;
+                }
+
+                if !evicted && !idle_timeout.is_zero() && last_input.elapsed() > idle_timeout {
+                    info!("Client was idle for too long.");
+                    ctx.room.send(RoomCommand::Evict { client_id, cause: DeathCause::Idle }).await;
+                    evicted = true;
+                }
+
+                ctx.room.send(RoomCommand::RecordBacklog { client_id, backlog: frames.len() }).await;
+
+                ping_seq += 1;
+                if pending_pings.len() >= MAX_PENDING_PINGS {
+                    pending_pings.pop_front();
+                }
+                pending_pings.push_back((ping_seq, Instant::now()));
+                if let Ok(json) = serde_json::to_string(&SnakeMessage::Ping { seq: ping_seq })
+                    && ws_tx.send(Message::Text(json.into())).await.is_err()
+                {
+                    break;
+                }
+            }
+
+            // Outbound: tick frames shared by the whole room
+            frame = frames.recv() => {
+                match frame {
+                    Ok(msg) => {
+                        if ws_tx.send(msg).await.is_err() { break; }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, backlog = frames.len(), "Client lagged behind.");
+                        counter!(monitoring::LAGGED_FRAMES, "room" => room.clone()).increment(skipped);
+                        ctx.room.send(RoomCommand::RecordLag { client_id, skipped }).await;
+
+                        if ctx.config.lag_policy == LagPolicy::Disconnect {
+                            break;
+                        }
+                        // Frames up to the keyframe's tick are ignored by the client.
+                        let Some(keyframe) = ctx.room.keyframe(client_id).await else { break };
+                        if ws_tx.send(keyframe).await.is_err() { break; }
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+
+            // Outbound: messages for this client only
+            msg = rx.recv() => {
+                // `None` means the snake is gone or another socket took it over.
+                let Some(msg) = msg else {
+                    // Flush frames already queued, the last one may report the death.
+                    while let Ok(frame) = frames.try_recv() {
+                        if ws_tx.send(frame).await.is_err() { break; }
+                    }
+                    break;
+                };
                 if ws_tx.send(msg).await.is_err() { break; }
             }
 
@@ -71,58 +321,196 @@ async fn handle_in_room_connection(socket: WebSocket, ctx: Arc<RoomContext>) {
             result = ws_rx.next() => {
                 match result {
                     Some(Ok(Message::Text(text))) => {
-                        if let Ok(SnakeMessage::MoveIntent { dx, dy }) = serde_json::from_str(&text) {
-                            let mut room_guard = ctx.room_manager.lock().await;
-                            room_guard.queue_move(&client_id, dx, dy);
+                        let received = |kind: &'static str| {
+                            counter!(monitoring::MESSAGES_RECEIVED, "room" => room.clone(), "type" => kind)
+                                .increment(1)
+                        };
+                        let cmd = match serde_json::from_str(&text) {
+                            Ok(SnakeMessage::MoveIntent { dir, tick }) => {
+                                received("move_intent");
+                                last_input = Instant::now();
+                                Some(RoomCommand::Input { client_id, dir, tick })
+                            }
+                            Ok(SnakeMessage::Pong { seq }) => {
+                                received("pong");
+                                // Any pong to a ping we sent shows the client is alive.
+                                if (1..=ping_seq).contains(&seq) {
+                                    last_pong = Instant::now();
+                                }
+                                match pending_pings.iter().position(|(sent_seq, _)| *sent_seq == seq) {
+                                    Some(i) => {
+                                        let sent_at = pending_pings[i].1;
+                                        // Older pings will not be answered anymore.
+                                        pending_pings.drain(..=i);
+                                        let rtt_ms = sent_at.elapsed().as_millis() as u32;
+                                        Some(RoomCommand::RecordRtt { client_id, rtt_ms })
+                                    }
+                                    None => None,
+                                }
+                            }
+                            Ok(_) => {
+                                received("unexpected");
+                                Some(RoomCommand::Reject {
+                                    client_id,
+                                    reason: InputRejection::Malformed,
+                                })
+                            }
+                            Err(err) => {
+                                received("undecodable");
+                                counter!(monitoring::DECODE_ERRORS, "room" => room.clone()).increment(1);
+                                debug!("Could not decode a message: {}", err);
+                                Some(RoomCommand::Reject {
+                                    client_id,
+                                    reason: InputRejection::Malformed,
+                                })
+                            }
+                        };
+
+                        if let Some(cmd) = cmd
+                            && !ctx.room.send(cmd).await
+                        {
+                            break;
                         }
                     }
                     Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(err)) => {
+                        debug!("Socket error: {}", err);
+                        break;
+                    }
                     _ => {}
                 }
             }
         }
     }
 
-    let _ = ws_tx.close().await;
+    if let Err(err) = ws_tx.close().await {
+        debug!("Could not close the socket: {}", err);
+    }
 
-    let mut room_guard = ctx.room_manager.lock().await;
-    room_guard.remove_client(&client_id);
-    let player_count = room_guard.clients.len();
-    let _ = ctx.lobby_tx.send(LobbyUpdate {
-        room_id: ctx.room_id,
-        player_count,
-    });
+    ctx.room.send(leave).await;
 
-    println!(
-        "Client {:?} disconnected and removed from state.",
-        client_id
-    );
+    info!("Client disconnected.");
+}
+
+/// Player socket that counts the messages and bytes sent through it.
+struct CountingSink {
+    ws_tx: SplitSink<WebSocket, Message>,
+    messages_sent: Counter,
+    bytes_sent: Counter,
+}
+
+impl CountingSink {
+    fn new(ws_tx: SplitSink<WebSocket, Message>, room: &str) -> Self {
+        Self {
+            ws_tx,
+            messages_sent: counter!(monitoring::MESSAGES_SENT, "room" => room.to_string()),
+            bytes_sent: counter!(monitoring::BYTES_SENT, "room" => room.to_string()),
+        }
+    }
+
+    async fn send(&mut self, msg: Message) -> Result<(), axum::Error> {
+        let bytes = match &msg {
+            Message::Text(text) => text.len(),
+            Message::Binary(bytes) | Message::Ping(bytes) | Message::Pong(bytes) => bytes.len(),
+            Message::Close(_) => 0,
+        };
+        self.ws_tx.send(msg).await?;
+        self.messages_sent.increment(1);
+        self.bytes_sent.increment(bytes as u64);
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), axum::Error> {
+        self.ws_tx.close().await
+    }
 }
 
 async fn handle_in_tui_connection(mut socket: WebSocket, tui_ctx: Arc<TuiContext>) {
     let mut rx = tui_ctx.lobby_tx.subscribe();
-    let (response_tx, mut response_rx) = mpsc::unbounded_channel();
-    tui_ctx.snapshot_req_tx.send(response_tx).unwrap();
-    let initial_snapshot = response_rx.recv().await.unwrap();
+    let initial_snapshot = status::lobby_snapshot(&tui_ctx.rooms.handles()).await;
+    debug!(rooms = initial_snapshot.len(), "Lobby connected.");
 
-    for update in initial_snapshot {
-        let json = serde_json::to_string(&update).unwrap();
-        if socket.send(Message::Text(json.into())).await.is_err() {
+    let leaderboards = tui_ctx
+        .profiles
+        .leaderboards(profiles::LOBBY_LEADERBOARD_SIZE);
+    let initial_messages = initial_snapshot
+        .into_iter()
+        .map(LobbyMessage::RoomUpdate)
+        .chain(std::iter::once(LobbyMessage::Leaderboard(leaderboards)));
+    for message in initial_messages {
+        let Some(msg) = encode_lobby(&message) else {
+            continue;
+        };
+        if socket.send(msg).await.is_err() {
             return; // TUI disconnected
         }
     }
 
-    // Listen for broadcasted updates and forward them to the TUI websocket
     loop {
-        match rx.recv().await {
-            Ok(update) => {
-                let json = serde_json::to_string(&update).unwrap();
-                if socket.send(Message::Text(json.into())).await.is_err() {
-                    break; // TUI disconnected
+        tokio::select! {
+            // Forward broadcasted updates to the TUI websocket
+            msg = rx.recv() => {
+                match msg {
+                    Ok(msg) => {
+                        let Some(encoded) = encode_lobby(&msg) else {
+                            continue;
+                        };
+                        if socket.send(encoded).await.is_err() {
+                            break; // TUI disconnected
+                        }
+                        if let LobbyMessage::ServerShutdown { .. } = msg {
+                            let _ = socket.close().await;
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Lobby connection lagged behind.");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
                 }
             }
-            Err(RecvError::Lagged(_)) => continue,
-            Err(RecvError::Closed) => break,
+
+            // Requests from the TUI, answered to it only
+            result = socket.recv() => {
+                let text = match result {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let reply = match serde_json::from_str(&text) {
+                    Ok(LobbyRequest::QuickPlay { mode, name }) => {
+                        match matchmaking::quick_play(&tui_ctx.rooms, mode, name.as_deref()).await {
+                            Ok(room_id) => {
+                                debug!(room_id, ?mode, "Quick play matched.");
+                                LobbyMessage::QuickPlayMatch { room_id }
+                            }
+                            Err(reason) => LobbyMessage::QuickPlayFailed {
+                                reason: reason.to_string(),
+                            },
+                        }
+                    }
+                    Err(err) => {
+                        debug!("Could not decode a lobby request: {}", err);
+                        continue;
+                    }
+                };
+                if let Some(encoded) = encode_lobby(&reply)
+                    && socket.send(encoded).await.is_err()
+                {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn encode_lobby(msg: &LobbyMessage) -> Option<Message> {
+    match serde_json::to_string(msg) {
+        Ok(json) => Some(Message::Text(json.into())),
+        Err(err) => {
+            warn!("Could not encode a lobby message: {}", err);
+            None
         }
     }
 }
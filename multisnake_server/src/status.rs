@@ -0,0 +1,133 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::room_actor::RoomHandle;
+use crate::room_manager::{RoomConfig, RoomManager};
+use crate::rooms::Rooms;
+use multisnake_shared::LobbyUpdate;
+
+pub struct StatusContext {
+    pub rooms: Arc<Rooms>,
+    pub started: Instant,
+    pub shutting_down: Arc<AtomicBool>,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    pub uptime_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct PlayerStatus {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub length: usize,
+    pub score: u32,
+    pub rtt_ms: Option<u32>,
+    pub bot: bool,
+    // Waiting for its player to reconnect.
+    pub disconnected: bool,
+}
+
+#[derive(Serialize)]
+pub struct RoomStatus {
+    pub room_id: u32,
+    pub locked: bool,
+    pub config: RoomConfig,
+    pub players: Vec<PlayerStatus>,
+    pub tick: u64,
+    pub measured_tick_ms: f32,
+    pub uptime_secs: u64,
+}
+
+impl RoomStatus {
+    pub fn of(room_id: u32, manager: &RoomManager) -> Self {
+        let mut players: Vec<PlayerStatus> = manager
+            .clients
+            .iter()
+            .filter_map(|(id, client)| {
+                let snake = manager.sim.snakes.get(id)?;
+                Some(PlayerStatus {
+                    id: *id,
+                    name: client.name.clone(),
+                    length: snake.body.len(),
+                    score: snake.score,
+                    rtt_ms: client.rtt_ms,
+                    bot: manager.bots.contains(id),
+                    disconnected: client.grace_ticks.is_some(),
+                })
+            })
+            .collect();
+        players.sort_by_key(|p| std::cmp::Reverse(p.length));
+
+        Self {
+            room_id,
+            locked: manager.config.locked(),
+            config: manager.config.clone(),
+            players,
+            tick: manager.sim.tick_count,
+            measured_tick_ms: manager.measured_tick_ms,
+            uptime_secs: manager.created.elapsed().as_secs(),
+        }
+    }
+}
+
+/// Player counts of every public room, as sent to the room selector.
+pub async fn lobby_snapshot(rooms: &[RoomHandle]) -> Vec<LobbyUpdate> {
+    let mut snapshot = Vec::new();
+    for room in rooms.iter().filter(|room| !room.private) {
+        if let Some(update) = room.snapshot().await {
+            snapshot.push(update);
+        }
+    }
+    snapshot
+}
+
+/// Fails with 503 once the server started shutting down.
+pub async fn health_handler(State(ctx): State<Arc<StatusContext>>) -> Response {
+    let shutting_down = ctx.shutting_down.load(Ordering::SeqCst);
+    let health = Health {
+        status: if shutting_down { "shutting_down" } else { "ok" },
+        uptime_secs: ctx.started.elapsed().as_secs(),
+    };
+    let code = if shutting_down {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (code, Json(health)).into_response()
+}
+
+/// Private rooms are only listed by `/rooms/{id}`.
+pub async fn rooms_handler(State(ctx): State<Arc<StatusContext>>) -> Json<Vec<RoomStatus>> {
+    let mut rooms = Vec::new();
+    for room in ctx.rooms.handles().iter().filter(|room| !room.private) {
+        if let Some(status) = room.status().await {
+            rooms.push(status);
+        }
+    }
+    Json(rooms)
+}
+
+pub async fn room_handler(
+    Path(room_id): Path<u32>,
+    State(ctx): State<Arc<StatusContext>>,
+) -> Response {
+    let Some(ctx) = ctx.rooms.get(room_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match ctx.room.status().await {
+        Some(status) => Json(status).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
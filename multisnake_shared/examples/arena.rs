@@ -0,0 +1,145 @@
+//! Plays bots against each other in a room without network and prints their
+//! win rates, average length and survival:
+//!
+//! `cargo run -p multisnake_shared --example arena -- lookahead,greedy --games 100`
+
+use clap::Parser;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::process::ExitCode;
+use uuid::Uuid;
+
+use multisnake_shared::controller::{BotLevel, Bots};
+use multisnake_shared::simulation::{Event, Input, Simulation};
+
+#[derive(Parser)]
+struct Args {
+    /// One snake per level, at least two, e.g. `lookahead,greedy` or `lookahead,lookahead`.
+    #[arg(value_enum, value_delimiter = ',', required = true)]
+    levels: Vec<BotLevel>,
+    /// How many games to play.
+    #[arg(long, default_value = "100")]
+    games: u32,
+    /// Games still running after this many ticks are won by the longest snake.
+    #[arg(long, default_value = "5000")]
+    max_ticks: u64,
+    /// Only changes how long new snakes stay ghosts.
+    #[arg(long, default_value = "100")]
+    tick_duration_ms: u32,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    // A lone snake would have no one to play against, its game would end at once.
+    if args.levels.len() < 2 {
+        eprintln!("The arena needs at least two bots, e.g. `lookahead,greedy`.");
+        return ExitCode::FAILURE;
+    }
+    print!(
+        "{}",
+        run(
+            args.tick_duration_ms,
+            &args.levels,
+            args.games,
+            args.max_ticks
+        )
+    );
+    ExitCode::SUCCESS
+}
+
+/// How the snakes of one controller fared over all the games.
+#[derive(Default)]
+struct Record {
+    snakes: u64,
+    wins: u64,
+    // Length of each snake when it died or when the game ended.
+    total_length: u64,
+    total_survival_ticks: u64,
+}
+
+/// Plays `games` games between `levels`, one snake each, in a simulated room
+/// without players. A game ends when at most one snake is left or
+/// after `max_ticks`, the longest snake then wins. Win rates are per snake,
+/// a level playing several snakes can win at most once per game. Returns the report.
+fn run(tick_duration_ms: u32, levels: &[BotLevel], games: u32, max_ticks: u64) -> String {
+    let mut records: BTreeMap<&str, Record> = BTreeMap::new();
+    let mut draws = 0;
+    let mut total_ticks = 0;
+
+    for _ in 0..games {
+        // Bots only turn once nothing is queued, a depth of one is enough.
+        let mut room = Simulation::new(tick_duration_ms, 1, rand::random());
+        let mut bots = Bots::default();
+        let mut seats: HashMap<Uuid, BotLevel> = HashMap::new();
+        for level in levels {
+            let id = Uuid::new_v4();
+            room.apply(&Input::Join { id });
+            bots.insert(id, level.controller());
+            seats.insert(id, *level);
+        }
+
+        let mut final_lengths: HashMap<Uuid, usize> = HashMap::new();
+        let mut survival: HashMap<Uuid, u64> = HashMap::new();
+        while room.tick_count < max_ticks && room.snakes.len() > 1 {
+            let turns = bots.steer(&room);
+            for event in room.step(&turns) {
+                if let Event::Died { id, length, .. } = event {
+                    bots.remove(&id);
+                    final_lengths.insert(id, length);
+                    survival.insert(id, room.tick_count);
+                }
+            }
+        }
+        total_ticks += room.tick_count;
+        for (id, snake) in &room.snakes {
+            final_lengths.insert(*id, snake.body.len());
+            survival.insert(*id, room.tick_count);
+        }
+
+        let longest = room.snakes.values().map(|s| s.body.len()).max();
+        let mut leaders = room
+            .snakes
+            .iter()
+            .filter(|(_, s)| Some(s.body.len()) == longest)
+            .map(|(id, _)| *id);
+        // Snakes of the same length share a draw.
+        let winner = match (leaders.next(), leaders.next()) {
+            (Some(id), None) => Some(id),
+            _ => None,
+        };
+        if winner.is_none() {
+            draws += 1;
+        }
+
+        for (id, level) in &seats {
+            let record = records.entry(level.as_str()).or_default();
+            record.snakes += 1;
+            record.wins += u64::from(winner == Some(*id));
+            record.total_length += final_lengths.get(id).copied().unwrap_or(0) as u64;
+            record.total_survival_ticks += survival.get(id).copied().unwrap_or(0);
+        }
+    }
+
+    let games = u64::from(games.max(1));
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "{} games, {:.0} ticks per game, {} draws",
+        games,
+        total_ticks as f64 / games as f64,
+        draws
+    );
+    for (name, record) in &records {
+        let snakes = record.snakes.max(1) as f64;
+        let _ = writeln!(
+            report,
+            "{:<10} wins {:>5} ({:>5.1}%), avg length {:>6.1}, avg survival {:>7.1} ticks",
+            name,
+            record.wins,
+            record.wins as f64 * 100.0 / snakes,
+            record.total_length as f64 / snakes,
+            record.total_survival_ticks as f64 / snakes
+        );
+    }
+    report
+}
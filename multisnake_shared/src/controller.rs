@@ -0,0 +1,353 @@
+//! Snake AIs: anything that picks the next direction from what it sees of the room.
+//! Used by the server's bots, by headless clients and by offline matches.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use uuid::Uuid;
+
+use crate::rules::{self, idx, is_in_bounds};
+use crate::simulation::{Input, Simulation};
+use crate::{Direction, GRID_H, GRID_W, Pos};
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+];
+// Chance of a wandering snake keeping its direction when that is safe.
+const KEEP_DIRECTION: f64 = 0.8;
+
+/// Read-only view of the room given to a controller before each tick.
+pub struct RoomView<'a> {
+    pub my_id: Uuid,
+    // Body of the controlled snake, head first.
+    pub snake: &'a VecDeque<Pos>,
+    // Direction the controlled snake last moved in.
+    pub dir: Direction,
+    // Ghosts cannot eat and nothing collides with them.
+    pub ghost: bool,
+    pub food: Pos,
+    // Every snake in the room, the controlled one included.
+    pub snakes: &'a [(Uuid, &'a VecDeque<Pos>)],
+    // How many snakes cover each cell, indexed with `rules::idx`.
+    pub grid: &'a [u8],
+}
+
+impl RoomView<'_> {
+    pub fn head(&self) -> Option<Pos> {
+        self.snake.front().copied()
+    }
+
+    pub fn is_free(&self, p: Pos) -> bool {
+        is_in_bounds(&p) && self.grid[idx(&p)] == 0
+    }
+
+    /// Directions that do not run into a wall or a snake on the next tick.
+    pub fn safe_directions(&self) -> Vec<Direction> {
+        let Some(head) = self.head() else {
+            return Vec::new();
+        };
+        DIRECTIONS
+            .into_iter()
+            .filter(|d| {
+                !rules::is_reversal(self.dir, *d) && self.is_free(rules::next_head(head, *d))
+            })
+            .collect()
+    }
+
+    /// Whether another snake's head could move onto `p` on the next tick.
+    pub fn contested(&self, p: Pos) -> bool {
+        self.snakes
+            .iter()
+            .filter(|(id, _)| *id != self.my_id)
+            .filter_map(|(_, snake)| snake.front())
+            .any(|head| distance(*head, p) == 1)
+    }
+}
+
+/// Something that steers a snake.
+pub trait SnakeController: Send {
+    /// Short name shown in scoreboards and reports.
+    fn name(&self) -> &str;
+
+    /// Direction for the next tick. A reversal is dropped by the rules, the
+    /// snake then goes straight on.
+    fn choose(&mut self, view: &RoomView) -> Direction;
+}
+
+/// The built-in controllers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum BotLevel {
+    /// Wanders around, only avoiding what it would hit on the next tick.
+    Random,
+    /// Heads straight for the food, only avoiding what it would hit on the next tick.
+    Greedy,
+    /// Follows the shortest path to the food, unless the snake would not fit
+    /// in the space the path leads to.
+    Lookahead,
+    /// Ignores the food and always goes where there is the most room.
+    Survivor,
+}
+
+impl BotLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BotLevel::Random => "random",
+            BotLevel::Greedy => "greedy",
+            BotLevel::Lookahead => "lookahead",
+            BotLevel::Survivor => "survivor",
+        }
+    }
+
+    pub fn controller(self) -> Box<dyn SnakeController> {
+        match self {
+            BotLevel::Random => Box::new(RandomController),
+            BotLevel::Greedy => Box::new(GreedyController),
+            BotLevel::Lookahead => Box::new(LookaheadController),
+            BotLevel::Survivor => Box::new(SurvivorController),
+        }
+    }
+}
+
+/// The controllers playing some of the snakes of a simulation, by snake id.
+#[derive(Default)]
+pub struct Bots {
+    controllers: BTreeMap<Uuid, Box<dyn SnakeController>>,
+}
+
+impl Bots {
+    pub fn insert(&mut self, id: Uuid, controller: Box<dyn SnakeController>) {
+        self.controllers.insert(id, controller);
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<Box<dyn SnakeController>> {
+        self.controllers.remove(id)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.controllers.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.controllers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.controllers.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.controllers.keys()
+    }
+
+    /// Asks every controller for its next turn. Only snakes without a queued
+    /// turn are steered, so that a bot never acts on a stale decision.
+    pub fn steer(&mut self, sim: &Simulation) -> Vec<Input> {
+        if sim.paused {
+            return Vec::new();
+        }
+
+        // Bots steer clear of ghosts too, a ghost overlapping a snake when it
+        // becomes solid kills both.
+        let mut grid = sim.occupied.clone();
+        for snake in sim.snakes.values().filter(|s| s.ghost_ticks > 0) {
+            for p in &snake.body {
+                grid[idx(p)] += 1;
+            }
+        }
+        let snakes: Vec<(Uuid, &VecDeque<Pos>)> = sim
+            .snakes
+            .iter()
+            .map(|(id, snake)| (*id, &snake.body))
+            .collect();
+
+        let mut turns = Vec::new();
+        for (id, controller) in self.controllers.iter_mut() {
+            let Some(snake) = sim.snakes.get(id) else {
+                continue;
+            };
+            if !snake.input_queue.is_empty() {
+                continue;
+            }
+            let view = RoomView {
+                my_id: *id,
+                snake: &snake.body,
+                dir: snake.dir,
+                ghost: snake.ghost_ticks > 0,
+                food: sim.food,
+                snakes: &snakes,
+                grid: &grid,
+            };
+            let dir = controller.choose(&view);
+            turns.push(Input::Turn { id: *id, dir });
+
+            // Keeps the next bots from moving into the same cell.
+            let next = rules::next_head(snake.body[0], dir);
+            if is_in_bounds(&next) {
+                grid[idx(&next)] += 1;
+            }
+        }
+        turns
+    }
+}
+
+pub struct RandomController;
+
+impl SnakeController for RandomController {
+    fn name(&self) -> &str {
+        BotLevel::Random.as_str()
+    }
+
+    fn choose(&mut self, view: &RoomView) -> Direction {
+        wander(view)
+    }
+}
+
+pub struct GreedyController;
+
+impl SnakeController for GreedyController {
+    fn name(&self) -> &str {
+        BotLevel::Greedy.as_str()
+    }
+
+    fn choose(&mut self, view: &RoomView) -> Direction {
+        // Ghosts cannot eat, they wander until they become solid.
+        let Some(head) = view.head().filter(|_| !view.ghost) else {
+            return wander(view);
+        };
+        view.safe_directions()
+            .into_iter()
+            .min_by_key(|d| distance(rules::next_head(head, *d), view.food))
+            .unwrap_or(view.dir)
+    }
+}
+
+pub struct LookaheadController;
+
+impl SnakeController for LookaheadController {
+    fn name(&self) -> &str {
+        BotLevel::Lookahead.as_str()
+    }
+
+    fn choose(&mut self, view: &RoomView) -> Direction {
+        let Some(head) = view.head().filter(|_| !view.ghost) else {
+            return wander(view);
+        };
+        let safe = view.safe_directions();
+        // Cells another head may move onto are only taken when there is no other way.
+        let uncontested: Vec<Direction> = safe
+            .iter()
+            .copied()
+            .filter(|d| !view.contested(rules::next_head(head, *d)))
+            .collect();
+        let allowed = if uncontested.is_empty() {
+            &safe
+        } else {
+            &uncontested
+        };
+
+        if let Some(first) = first_step_to(view, head, view.food, allowed)
+            && reachable(view, rules::next_head(head, first)) >= view.snake.len()
+        {
+            return first;
+        }
+        // No safe path to the food, go where there is the most room.
+        most_room(view, allowed)
+    }
+}
+
+pub struct SurvivorController;
+
+impl SnakeController for SurvivorController {
+    fn name(&self) -> &str {
+        BotLevel::Survivor.as_str()
+    }
+
+    fn choose(&mut self, view: &RoomView) -> Direction {
+        most_room(view, &view.safe_directions())
+    }
+}
+
+/// Random safe direction, mostly keeping the current one.
+fn wander(view: &RoomView) -> Direction {
+    let safe = view.safe_directions();
+    if safe.is_empty() || (safe.contains(&view.dir) && rand::random_bool(KEEP_DIRECTION)) {
+        return view.dir;
+    }
+    safe[rand::random_range(0..safe.len())]
+}
+
+/// The direction among `allowed` leading to the largest free area, straight on
+/// if there is none.
+fn most_room(view: &RoomView, allowed: &[Direction]) -> Direction {
+    let Some(head) = view.head() else {
+        return view.dir;
+    };
+    allowed
+        .iter()
+        .copied()
+        .max_by_key(|d| reachable(view, rules::next_head(head, *d)))
+        .unwrap_or(view.dir)
+}
+
+fn distance(a: Pos, b: Pos) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+fn neighbours(p: Pos) -> impl Iterator<Item = Pos> {
+    DIRECTIONS.into_iter().map(move |d| rules::next_head(p, d))
+}
+
+/// First direction of the shortest free path from `head` to `target`, found by
+/// a breadth-first search starting with the `allowed` directions.
+fn first_step_to(
+    view: &RoomView,
+    head: Pos,
+    target: Pos,
+    allowed: &[Direction],
+) -> Option<Direction> {
+    let mut first_step: Vec<Option<Direction>> = vec![None; (GRID_W * GRID_H) as usize];
+    let mut queue = VecDeque::new();
+    for &dir in allowed {
+        let p = rules::next_head(head, dir);
+        first_step[idx(&p)] = Some(dir);
+        queue.push_back(p);
+    }
+
+    while let Some(p) = queue.pop_front() {
+        if p == target {
+            return first_step[idx(&p)];
+        }
+        for next in neighbours(p) {
+            if next != head && view.is_free(next) && first_step[idx(&next)].is_none() {
+                first_step[idx(&next)] = first_step[idx(&p)];
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// How many free cells can be reached from `start`, itself included.
+fn reachable(view: &RoomView, start: Pos) -> usize {
+    if !view.is_free(start) {
+        return 0;
+    }
+    let mut seen = vec![false; (GRID_W * GRID_H) as usize];
+    let mut stack = vec![start];
+    seen[idx(&start)] = true;
+    let mut count = 0;
+
+    while let Some(p) = stack.pop() {
+        count += 1;
+        for next in neighbours(p) {
+            if view.is_free(next) && !seen[idx(&next)] {
+                seen[idx(&next)] = true;
+                stack.push(next);
+            }
+        }
+    }
+    count
+}
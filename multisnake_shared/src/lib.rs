@@ -2,6 +2,11 @@ use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, VecDeque};
 use uuid::Uuid;
 
+pub mod controller;
+pub mod replay;
+pub mod rules;
+pub mod simulation;
+
 pub const N_ROOMS: u32 = 3;
 
 pub const GRID_W: i32 = 50;
@@ -13,24 +18,70 @@ pub struct Pos {
     pub y: i32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    pub fn from_delta(delta: (i32, i32)) -> Option<Self> {
+        match delta {
+            (0, -1) => Some(Direction::Up),
+            (0, 1) => Some(Direction::Down),
+            (-1, 0) => Some(Direction::Left),
+            (1, 0) => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", content = "data")]
 pub enum SnakeMessage {
-    /// Sent to a client immediately upon connection
+    /// Sent to a client immediately upon connection, and again as a keyframe
+    /// when it fell too far behind
     OnJoin {
         my_id: Uuid,
+        // Secret used to reclaim this snake after a dropped connection
+        session_token: Uuid,
         // Snapshot of all existing snakes
         snakes: HashMap<Uuid, VecDeque<Pos>>,
 
         food: Pos,
 
         tick_duration_ms: u32,
+        // How many turns the server buffers per player
+        input_queue_depth: u32,
+        // Tick the snapshot was taken at
+        tick: u64,
     },
 
     /// The room update sent to clients every tick
     TickUpdate {
-        // Only sends changes in position (dx, dy) for living snakes
-        moves: HashMap<Uuid, (i32, i32)>,
+        tick: u64,
+        // Only sends the direction each living snake moved in
+        moves: HashMap<Uuid, Direction>,
         food: Pos,
         // List of IDs that died this tick
         deaths: Vec<Uuid>,
@@ -42,12 +93,173 @@ pub enum SnakeMessage {
         ghosts: Vec<Uuid>,
     },
 
-    /// Client -> Server: "I want to go this way"
-    MoveIntent { dx: i32, dy: i32 },
+    /// Per-player stats, sent every few ticks
+    Scoreboard { entries: Vec<ScoreEntry> },
+
+    /// Average time between ticks actually achieved by the server, sent every few ticks
+    TickRate { measured_tick_ms: f32 },
+
+    /// The server is going down in `seconds`, no reconnect should be attempted after that
+    ServerShutdown { reason: String, seconds: u32 },
+
+    /// The player was kicked out of the room, the socket closes right after
+    Kicked { reason: String },
+
+    /// Message from the server operators, shown to everyone in the room
+    ServerNotice { message: String },
+
+    /// Server -> Client heartbeat, answered with a `Pong` carrying the same `seq`
+    Ping { seq: u32 },
+
+    /// Client -> Server: heartbeat answer
+    Pong { seq: u32 },
+
+    /// Client -> Server: "I want to go this way", stamped with the last tick the client saw
+    MoveIntent { dir: Direction, tick: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoreEntry {
+    pub id: Uuid,
+    pub length: usize,
+    // Last measured round trip time, `None` until the first heartbeat is answered.
+    pub rtt_ms: Option<u32>,
+    // Played by the server.
+    #[serde(default)]
+    pub bot: bool,
+    // Of named players in ranked rooms.
+    #[serde(default)]
+    pub rating: Option<u32>,
+}
+
+/// Rule set a room is played with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    #[default]
+    Classic,
+    /// Ticks twice as often as classic rooms.
+    Fast,
+}
+
+impl GameMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameMode::Classic => "classic",
+            GameMode::Fast => "fast",
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct LobbyUpdate {
     pub room_id: u32,
+    // Players only, bots are counted separately.
     pub player_count: usize,
+    pub bots: usize,
+    pub max_players: usize,
+    pub mode: GameMode,
+    // Joining needs a password.
+    pub locked: bool,
+    // Only players logged in to an account may join.
+    #[serde(default)]
+    pub requires_account: bool,
+    // Rounds of the room change the players' ratings.
+    #[serde(default)]
+    pub ranked: bool,
+    // Average rating of the named players in a ranked room, `None` without any.
+    #[serde(default)]
+    pub rating: Option<u32>,
+}
+
+/// Body of the server's `/accounts/register` and `/accounts/login` routes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AccountRequest {
+    pub name: String,
+    pub password: String,
+}
+
+/// Answer to a login, `token` goes in the `auth` parameter of room URLs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginResponse {
+    // As registered, the login may have used another case.
+    pub name: String,
+    pub token: Uuid,
+}
+
+/// What the server remembers of a named player across games.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub games_played: u64,
+    // Snakes that ran into this player's snakes.
+    pub total_kills: u64,
+    pub best_length: usize,
+    pub best_survival_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: PlayerStats,
+}
+
+/// Skill of a named player, from the rounds of ranked rooms.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RatingEntry {
+    pub name: String,
+    pub rating: u32,
+    pub rounds: u64,
+}
+
+/// Best players by length, then kills, then survival time.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Leaderboards {
+    pub all_time: Vec<LeaderboardEntry>,
+    // Games of the current UTC day only.
+    pub daily: Vec<LeaderboardEntry>,
+    // Highest ratings first.
+    #[serde(default)]
+    pub ratings: Vec<RatingEntry>,
+}
+
+/// Messages sent to clients watching the lobby.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum LobbyMessage {
+    RoomUpdate(LobbyUpdate),
+    ServerShutdown {
+        reason: String,
+        seconds: u32,
+    },
+    ServerNotice {
+        message: String,
+    },
+    /// Answer to `LobbyRequest::QuickPlay`, the client joins this room.
+    QuickPlayMatch {
+        room_id: u32,
+    },
+    /// Every room is full and no new one could be opened.
+    QuickPlayFailed {
+        reason: String,
+    },
+    /// Sent on connecting and whenever a named player's game ends.
+    Leaderboard(Leaderboards),
+    /// A room quick play opened was left empty and closed.
+    RoomClosed {
+        room_id: u32,
+    },
+}
+
+/// Messages sent by clients watching the lobby.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum LobbyRequest {
+    /// Asks the server to pick a room, of `mode` if given. With a `name`,
+    /// ranked rooms of players rated like it come first.
+    QuickPlay {
+        mode: Option<GameMode>,
+        #[serde(default)]
+        name: Option<String>,
+    },
 }
@@ -0,0 +1,70 @@
+//! Replay files: gzipped JSON lines, one `ReplayRecord` per line. A file
+//! starts with a `Header` and a `Keyframe`, then has a `Tick` per tick and a
+//! `Keyframe` every `KEYFRAME_INTERVAL_TICKS` to seek from.
+
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::simulation::Input;
+use crate::{GameMode, Pos, SnakeMessage};
+
+pub const KEYFRAME_INTERVAL_TICKS: u64 = 100;
+pub const EXTENSION: &str = "replay.gz";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ReplayRecord {
+    Header {
+        room_id: u32,
+        mode: GameMode,
+        tick_duration_ms: u32,
+        // Seed the room started from, the file may begin later in its history.
+        seed: u64,
+        started_unix_secs: u64,
+    },
+    /// Everything needed to show the room from this tick on.
+    Keyframe {
+        tick: u64,
+        snakes: HashMap<Uuid, VecDeque<Pos>>,
+        food: Pos,
+        ghosts: Vec<Uuid>,
+        names: HashMap<Uuid, String>,
+    },
+    Tick {
+        // Inputs the simulation went through, the joins applied between ticks first.
+        inputs: Vec<Input>,
+        // The `TickUpdate` sent to the players.
+        update: SnakeMessage,
+        // Names of the snakes that joined.
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        names: HashMap<Uuid, String>,
+    },
+}
+
+/// Reads every record of a replay file. A file still being written ends with
+/// a partial line, reading stops before it.
+pub fn read(path: &Path) -> io::Result<Vec<ReplayRecord>> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+    }
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a replay file",
+        ));
+    }
+    Ok(records)
+}
@@ -0,0 +1,112 @@
+//! Movement rules shared by the server simulation and the client prediction.
+
+use std::collections::VecDeque;
+
+use crate::{Direction, GRID_H, GRID_W, Pos};
+
+pub fn idx(p: &Pos) -> usize {
+    p.y as usize * GRID_W as usize + p.x as usize
+}
+
+pub fn is_in_bounds(p: &Pos) -> bool {
+    p.x >= 0 && p.x < GRID_W && p.y >= 0 && p.y < GRID_H
+}
+
+/// Whether turning from `current` to `next` would make the snake go back into itself.
+pub fn is_reversal(current: Direction, next: Direction) -> bool {
+    next == current.opposite()
+}
+
+/// Queues a turn, checking it against the last queued direction (or `current`
+/// when nothing is queued). Reversals, repeats and turns beyond `depth` are dropped.
+pub fn queue_turn(
+    queue: &mut VecDeque<Direction>,
+    current: Direction,
+    turn: Direction,
+    depth: usize,
+) -> bool {
+    let last = queue.back().copied().unwrap_or(current);
+    if queue.len() >= depth || turn == last || is_reversal(last, turn) {
+        return false;
+    }
+    queue.push_back(turn);
+    true
+}
+
+/// Direction for the next step: the oldest queued turn, or straight on.
+pub fn next_direction(queue: &mut VecDeque<Direction>, current: Direction) -> Direction {
+    queue.pop_front().unwrap_or(current)
+}
+
+/// Direction the snake is currently facing, derived from its first two segments.
+pub fn heading(snake: &VecDeque<Pos>) -> Option<Direction> {
+    let head = snake.front()?;
+    let neck = snake.get(1)?;
+    Direction::from_delta((head.x - neck.x, head.y - neck.y))
+}
+
+pub fn next_head(head: Pos, dir: Direction) -> Pos {
+    let (dx, dy) = dir.delta();
+    Pos {
+        x: head.x + dx,
+        y: head.y + dy,
+    }
+}
+
+/// Ghost snakes pass over food without eating it.
+pub fn eats(new_head: Pos, food: Pos, is_ghost: bool) -> bool {
+    new_head == food && !is_ghost
+}
+
+/// Moves the snake onto `new_head`, returning the freed tail cell unless it grew.
+pub fn advance(snake: &mut VecDeque<Pos>, new_head: Pos, grow: bool) -> Option<Pos> {
+    snake.push_front(new_head);
+    if grow { None } else { snake.pop_back() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_turn_drops_reversals_repeats_and_overflow() {
+        let mut queue = VecDeque::new();
+        assert!(!queue_turn(&mut queue, Direction::Up, Direction::Down, 2));
+        assert!(!queue_turn(&mut queue, Direction::Up, Direction::Up, 2));
+        assert!(queue_turn(&mut queue, Direction::Up, Direction::Left, 2));
+        // Checked against the last queued turn, not the heading.
+        assert!(!queue_turn(&mut queue, Direction::Up, Direction::Right, 2));
+        assert!(!queue_turn(&mut queue, Direction::Up, Direction::Left, 2));
+        assert!(queue_turn(&mut queue, Direction::Up, Direction::Down, 2));
+        assert!(!queue_turn(&mut queue, Direction::Up, Direction::Left, 2));
+        assert_eq!(queue, [Direction::Left, Direction::Down]);
+    }
+
+    #[test]
+    fn next_direction_takes_the_oldest_turn_or_goes_straight() {
+        let mut queue = VecDeque::from([Direction::Left, Direction::Down]);
+        assert_eq!(next_direction(&mut queue, Direction::Up), Direction::Left);
+        assert_eq!(next_direction(&mut queue, Direction::Left), Direction::Down);
+        assert_eq!(next_direction(&mut queue, Direction::Down), Direction::Down);
+    }
+
+    #[test]
+    fn advance_keeps_the_tail_when_growing() {
+        let mut snake = VecDeque::from([Pos { x: 5, y: 5 }, Pos { x: 5, y: 6 }]);
+        let head = next_head(snake[0], Direction::Up);
+        assert_eq!(head, Pos { x: 5, y: 4 });
+        assert_eq!(advance(&mut snake, head, true), None);
+        assert_eq!(snake.len(), 3);
+        let head = next_head(snake[0], Direction::Left);
+        assert_eq!(advance(&mut snake, head, false), Some(Pos { x: 5, y: 6 }));
+        assert_eq!(heading(&snake), Some(Direction::Left));
+    }
+
+    #[test]
+    fn ghosts_do_not_eat() {
+        let food = Pos { x: 3, y: 3 };
+        assert!(eats(food, food, false));
+        assert!(!eats(food, food, true));
+        assert!(!eats(Pos { x: 3, y: 4 }, food, false));
+    }
+}
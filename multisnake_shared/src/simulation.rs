@@ -0,0 +1,641 @@
+//! The rules of a room as a pure state machine: inputs go in, the room
+//! advances one tick per `step` and reports what happened as events. No
+//! sockets, clocks or global randomness, so the same seed and inputs always
+//! give the same room. The server wraps it with connections, the client runs
+//! it for offline games and `controller::Bots` steers snakes in it.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use uuid::Uuid;
+
+use crate::rules::{self, idx, is_in_bounds};
+use crate::{Direction, GRID_H, GRID_W, Pos, SnakeMessage};
+
+const GHOST_TIME_MS: u32 = 8000;
+const PADDING: i32 = 15;
+const INITIAL_SNAKE_LENGTH: u32 = 5;
+
+/// Why a snake was removed from its room.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathCause {
+    /// Ran into the edge of the grid.
+    Wall,
+    /// Ran into a snake, possibly itself.
+    Collision,
+    /// Its player did not reconnect within `reconnect_grace_ms`.
+    Abandoned,
+    /// Its player sent no input for `idle_timeout_ms`.
+    Idle,
+    /// Its player was kicked by an admin or for sending too many rejected inputs.
+    Kicked,
+    /// A bot making room for a player.
+    Replaced,
+}
+
+impl DeathCause {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeathCause::Wall => "wall",
+            DeathCause::Collision => "collision",
+            DeathCause::Abandoned => "abandoned",
+            DeathCause::Idle => "idle",
+            DeathCause::Kicked => "kicked",
+            DeathCause::Replaced => "replaced",
+        }
+    }
+}
+
+/// Something from outside that changes the room.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Input {
+    /// A snake appears at a random spot, as a ghost.
+    Join { id: Uuid },
+    /// Queues a turn. Reversals, repeats and turns over the queue depth are dropped.
+    Turn { id: Uuid, dir: Direction },
+    /// Kills a snake.
+    Evict { id: Uuid, cause: DeathCause },
+    /// Keeps a snake in place, e.g. while its player is away.
+    Freeze { id: Uuid, frozen: bool },
+    /// Keeps every snake in place and stops every timer.
+    Pause { paused: bool },
+    /// Moves the food somewhere else.
+    RespawnFood,
+}
+
+/// Something that happened during a step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A snake that joined since the last step, announced to everyone.
+    Spawned {
+        id: Uuid,
+        body: VecDeque<Pos>,
+    },
+    Moved {
+        id: Uuid,
+        dir: Direction,
+    },
+    Ate {
+        id: Uuid,
+    },
+    FoodSpawned {
+        food: Pos,
+    },
+    Died {
+        id: Uuid,
+        cause: DeathCause,
+        length: usize,
+        // Snake it ran into, `None` for walls, itself or evictions.
+        killer: Option<Uuid>,
+    },
+}
+
+pub struct RoomSnake {
+    // Head first.
+    pub body: VecDeque<Pos>,
+    pub dir: Direction,
+    // Turns not applied yet, one is consumed per tick.
+    pub input_queue: VecDeque<Direction>,
+    pub ghost_ticks: u32,
+    // Food eaten by this snake.
+    pub score: u32,
+    // Stays in place while set, e.g. while its player is away.
+    pub frozen: bool,
+}
+
+pub struct Simulation {
+    // Sorted so that every pass over the snakes happens in the same order.
+    pub snakes: BTreeMap<Uuid, RoomSnake>,
+
+    // 2D grid flattened to 1D. Values > 1 indicate collision.
+    pub occupied: Vec<u8>,
+
+    pub food: Pos,
+
+    // New snakes to be announced next tick.
+    pub pending_joins: BTreeMap<Uuid, VecDeque<Pos>>,
+
+    // Snakes to be removed next tick.
+    pub pending_evictions: BTreeMap<Uuid, DeathCause>,
+
+    pub tick_count: u64,
+
+    // Every snake stays in place until the room is resumed.
+    pub paused: bool,
+
+    // How many turns are buffered per snake.
+    pub input_queue_depth: usize,
+
+    // Ticks a new snake spends as a ghost.
+    ghost_time_ticks: u32,
+
+    // The only source of randomness, for food and spawns.
+    rng: StdRng,
+}
+
+impl Simulation {
+    pub fn new(tick_duration_ms: u32, input_queue_depth: u32, seed: u64) -> Self {
+        let mut sim = Self {
+            snakes: BTreeMap::new(),
+            occupied: vec![0; (GRID_W * GRID_H) as usize],
+            food: Pos { x: 5, y: 5 },
+            pending_joins: BTreeMap::new(),
+            pending_evictions: BTreeMap::new(),
+            tick_count: 0,
+            paused: false,
+            input_queue_depth: input_queue_depth as usize,
+            ghost_time_ticks: GHOST_TIME_MS / tick_duration_ms.max(1) + 1,
+            rng: StdRng::seed_from_u64(seed),
+        };
+        sim.food = sim.random_pos();
+        sim
+    }
+
+    /// Applies one input right away, e.g. a join that has to be in the
+    /// snapshot sent to the joining player. Returns whether it changed anything.
+    pub fn apply(&mut self, input: &Input) -> bool {
+        match *input {
+            Input::Join { id } => {
+                if self.snakes.contains_key(&id) {
+                    return false;
+                }
+                let body = self.initial_snake_segments(INITIAL_SNAKE_LENGTH);
+                self.snakes.insert(
+                    id,
+                    RoomSnake {
+                        body: body.clone(),
+                        dir: Direction::Up,
+                        input_queue: VecDeque::new(),
+                        ghost_ticks: self.ghost_time_ticks,
+                        score: 0,
+                        frozen: false,
+                    },
+                );
+                // Add to buffer so existing players see them next tick.
+                self.pending_joins.insert(id, body);
+                true
+            }
+            Input::Turn { id, dir } => match self.snakes.get_mut(&id) {
+                Some(snake) => rules::queue_turn(
+                    &mut snake.input_queue,
+                    snake.dir,
+                    dir,
+                    self.input_queue_depth,
+                ),
+                None => false,
+            },
+            Input::Evict { id, cause } => {
+                if !self.snakes.contains_key(&id) {
+                    return false;
+                }
+                self.pending_evictions.entry(id).or_insert(cause);
+                true
+            }
+            Input::Freeze { id, frozen } => match self.snakes.get_mut(&id) {
+                Some(snake) if snake.frozen != frozen => {
+                    snake.frozen = frozen;
+                    true
+                }
+                _ => false,
+            },
+            Input::Pause { paused } => {
+                let changed = self.paused != paused;
+                self.paused = paused;
+                changed
+            }
+            Input::RespawnFood => {
+                self.food = self.random_pos();
+                true
+            }
+        }
+    }
+
+    /// Applies `inputs` in order, then moves every snake one cell and removes
+    /// the dead ones.
+    pub fn step(&mut self, inputs: &[Input]) -> Vec<Event> {
+        for input in inputs {
+            self.apply(input);
+        }
+
+        let mut events: Vec<Event> = std::mem::take(&mut self.pending_joins)
+            .into_iter()
+            .map(|(id, body)| Event::Spawned { id, body })
+            .collect();
+        let mut dead: BTreeMap<Uuid, DeathCause> = std::mem::take(&mut self.pending_evictions);
+        let mut killers: BTreeMap<Uuid, Uuid> = BTreeMap::new();
+        let mut frozen = Vec::new();
+        let mut food_eaten = 0;
+
+        self.tick_count += 1;
+
+        // Calculate moves and wall collisions.
+        for (id, snake) in self.snakes.iter_mut() {
+            if dead.contains_key(id) {
+                continue;
+            }
+
+            // Nothing moves and no timer runs while paused.
+            if self.paused {
+                frozen.push(*id);
+                continue;
+            }
+
+            if snake.ghost_ticks > 0 {
+                snake.ghost_ticks -= 1;
+                if snake.ghost_ticks == 0 {
+                    // Mark occupied grid when ghost mode ends.
+                    for p in &snake.body {
+                        self.occupied[idx(p)] += 1;
+                    }
+                }
+            }
+
+            if snake.frozen {
+                frozen.push(*id);
+                continue;
+            }
+
+            let dir = rules::next_direction(&mut snake.input_queue, snake.dir);
+            let new_head = rules::next_head(*snake.body.front().unwrap(), dir);
+
+            // Wall check.
+            if !rules::is_in_bounds(&new_head) {
+                dead.insert(*id, DeathCause::Wall);
+                continue;
+            }
+
+            // Apply move logic (grow or move).
+            snake.dir = dir;
+
+            let ate = rules::eats(new_head, self.food, snake.ghost_ticks > 0);
+            if ate {
+                snake.score += 1;
+                food_eaten += 1;
+                events.push(Event::Ate { id: *id });
+            }
+            let old_tail = rules::advance(&mut snake.body, new_head, ate);
+
+            if snake.ghost_ticks == 0 {
+                // Update `occupied` grid.
+                self.occupied[idx(&new_head)] += 1;
+                if let Some(old_tail) = old_tail {
+                    self.occupied[idx(&old_tail)] -= 1;
+                }
+            }
+        }
+
+        for _ in 0..food_eaten {
+            self.food = self.random_pos();
+            events.push(Event::FoodSpawned { food: self.food });
+        }
+
+        // Snake-to-snake collision check.
+        for (id, snake) in &self.snakes {
+            if dead.contains_key(id) {
+                continue;
+            }
+
+            let head = snake.body.front().unwrap();
+
+            // > 1 means that there is a collision, we ignore ghost snakes.
+            if snake.ghost_ticks == 0 && self.occupied[idx(head)] > 1 {
+                dead.insert(*id, DeathCause::Collision);
+                if let Some(killer) = self.owner_of(head, id) {
+                    killers.insert(*id, killer);
+                }
+            } else if !frozen.contains(id) {
+                events.push(Event::Moved {
+                    id: *id,
+                    dir: snake.dir,
+                });
+            }
+        }
+
+        for (id, cause) in dead {
+            if let Some(snake) = self.remove(&id) {
+                events.push(Event::Died {
+                    id,
+                    cause,
+                    length: snake.body.len(),
+                    killer: killers.get(&id).copied(),
+                });
+            }
+        }
+
+        events
+    }
+
+    /// The `TickUpdate` telling players about the step that produced `events`.
+    pub fn tick_update(&self, events: &[Event]) -> SnakeMessage {
+        let mut moves = HashMap::new();
+        let mut deaths = Vec::new();
+        let mut eaters = Vec::new();
+        let mut new_snakes = HashMap::new();
+        for event in events {
+            match event {
+                Event::Spawned { id, body } => {
+                    new_snakes.insert(*id, body.clone());
+                }
+                Event::Moved { id, dir } => {
+                    moves.insert(*id, *dir);
+                }
+                Event::Ate { id } => eaters.push(*id),
+                Event::Died { id, .. } => deaths.push(*id),
+                Event::FoodSpawned { .. } => {}
+            }
+        }
+
+        SnakeMessage::TickUpdate {
+            tick: self.tick_count,
+            moves,
+            food: self.food,
+            deaths,
+            eaters,
+            new_snakes,
+            ghosts: self
+                .snakes
+                .iter()
+                .filter(|(_, snake)| snake.ghost_ticks > 0)
+                .map(|(id, _)| *id)
+                .collect(),
+        }
+    }
+
+    /// Every snake's body, as sent to joining players.
+    pub fn bodies(&self) -> HashMap<Uuid, VecDeque<Pos>> {
+        self.snakes
+            .iter()
+            .map(|(id, snake)| (*id, snake.body.clone()))
+            .collect()
+    }
+
+    /// The solid snake other than `except` covering `pos`.
+    fn owner_of(&self, pos: &Pos, except: &Uuid) -> Option<Uuid> {
+        self.snakes
+            .iter()
+            .find(|(id, snake)| *id != except && snake.ghost_ticks == 0 && snake.body.contains(pos))
+            .map(|(id, _)| *id)
+    }
+
+    /// Removes a snake, freeing its cells.
+    fn remove(&mut self, id: &Uuid) -> Option<RoomSnake> {
+        let snake = self.snakes.remove(id)?;
+        if snake.ghost_ticks == 0 {
+            for p in &snake.body {
+                assert!(is_in_bounds(p));
+                self.occupied[idx(p)] -= 1;
+            }
+        }
+        Some(snake)
+    }
+
+    fn random_pos(&mut self) -> Pos {
+        Pos {
+            x: self.rng.random_range(PADDING..GRID_W - PADDING),
+            y: self.rng.random_range(PADDING..GRID_H - PADDING),
+        }
+    }
+
+    fn initial_snake_segments(&mut self, length: u32) -> VecDeque<Pos> {
+        let start = self.random_pos();
+
+        (0..length)
+            .map(|i| Pos {
+                x: start.x,
+                y: start.y + i as i32,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_MS: u32 = 100;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn pos(x: i32, y: i32) -> Pos {
+        Pos { x, y }
+    }
+
+    /// Puts a solid snake in the room, `body` head first.
+    fn place(sim: &mut Simulation, id: Uuid, body: &[Pos], dir: Direction) {
+        for p in body {
+            sim.occupied[idx(p)] += 1;
+        }
+        sim.snakes.insert(
+            id,
+            RoomSnake {
+                body: body.iter().copied().collect(),
+                dir,
+                input_queue: VecDeque::new(),
+                ghost_ticks: 0,
+                score: 0,
+                frozen: false,
+            },
+        );
+    }
+
+    fn deaths(events: &[Event]) -> Vec<(Uuid, DeathCause, Option<Uuid>)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Died {
+                    id, cause, killer, ..
+                } => Some((*id, *cause, *killer)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Three snakes turning in a square, long enough for ghosts to turn solid.
+    fn play(seed: u64) -> (Vec<Vec<Event>>, Pos) {
+        let mut sim = Simulation::new(TICK_MS, 3, seed);
+        let turns = [
+            Direction::Left,
+            Direction::Down,
+            Direction::Right,
+            Direction::Up,
+        ];
+        let mut history = Vec::new();
+        for tick in 0..300u64 {
+            let mut inputs = Vec::new();
+            if tick < 3 {
+                inputs.push(Input::Join {
+                    id: id(tick as u128),
+                });
+            }
+            if tick % 4 == 0 {
+                for n in 0..3 {
+                    inputs.push(Input::Turn {
+                        id: id(n),
+                        dir: turns[((tick / 4 + n as u64) % 4) as usize],
+                    });
+                }
+            }
+            if tick == 150 {
+                inputs.push(Input::RespawnFood);
+            }
+            history.push(sim.step(&inputs));
+        }
+        (history, sim.food)
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_events() {
+        let (first, first_food) = play(42);
+        let (second, second_food) = play(42);
+        assert_eq!(first, second);
+        assert_eq!(first_food, second_food);
+        assert!(
+            first
+                .iter()
+                .flatten()
+                .any(|e| matches!(e, Event::Spawned { .. }))
+        );
+    }
+
+    #[test]
+    fn seeds_place_food_differently() {
+        let foods: Vec<Pos> = (0..8)
+            .map(|seed| Simulation::new(TICK_MS, 3, seed).food)
+            .collect();
+        assert!(foods.iter().any(|food| *food != foods[0]));
+    }
+
+    #[test]
+    fn join_spawns_a_ghost_announced_on_the_next_step() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        assert!(sim.apply(&Input::Join { id: id(1) }));
+        assert!(!sim.apply(&Input::Join { id: id(1) }));
+        assert!(sim.snakes[&id(1)].ghost_ticks > 0);
+        let events = sim.step(&[]);
+        assert!(matches!(events[0], Event::Spawned { id: spawned, .. } if spawned == id(1)));
+        assert!(
+            sim.step(&[])
+                .iter()
+                .all(|e| !matches!(e, Event::Spawned { .. }))
+        );
+    }
+
+    #[test]
+    fn wall_death_has_no_killer() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        place(
+            &mut sim,
+            id(1),
+            &[pos(0, 5), pos(1, 5), pos(2, 5)],
+            Direction::Left,
+        );
+        let events = sim.step(&[]);
+        assert_eq!(deaths(&events), [(id(1), DeathCause::Wall, None)]);
+        assert!(sim.snakes.is_empty());
+        assert!(sim.occupied.iter().all(|cell| *cell == 0));
+    }
+
+    #[test]
+    fn running_into_a_snake_credits_it() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        let body: Vec<Pos> = (10..15).map(|x| pos(x, 5)).collect();
+        place(&mut sim, id(1), &body, Direction::Left);
+        place(
+            &mut sim,
+            id(2),
+            &[pos(12, 4), pos(12, 3), pos(12, 2)],
+            Direction::Down,
+        );
+        let events = sim.step(&[]);
+        assert_eq!(
+            deaths(&events),
+            [(id(2), DeathCause::Collision, Some(id(1)))]
+        );
+        assert!(sim.snakes.contains_key(&id(1)));
+    }
+
+    #[test]
+    fn running_into_itself_has_no_killer() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        let body = [pos(5, 5), pos(6, 5), pos(6, 6), pos(5, 6), pos(4, 6)];
+        place(&mut sim, id(1), &body, Direction::Left);
+        let events = sim.step(&[Input::Turn {
+            id: id(1),
+            dir: Direction::Down,
+        }]);
+        assert_eq!(deaths(&events), [(id(1), DeathCause::Collision, None)]);
+    }
+
+    #[test]
+    fn evicted_snakes_die_with_the_given_cause() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        place(&mut sim, id(1), &[pos(5, 5), pos(5, 6)], Direction::Up);
+        let events = sim.step(&[Input::Evict {
+            id: id(1),
+            cause: DeathCause::Kicked,
+        }]);
+        assert_eq!(deaths(&events), [(id(1), DeathCause::Kicked, None)]);
+    }
+
+    #[test]
+    fn turns_beyond_the_queue_depth_are_dropped() {
+        let mut sim = Simulation::new(TICK_MS, 2, 1);
+        place(&mut sim, id(1), &[pos(5, 5), pos(5, 6)], Direction::Up);
+        let turn = |dir| Input::Turn { id: id(1), dir };
+        assert!(!sim.apply(&turn(Direction::Down)));
+        assert!(!sim.apply(&turn(Direction::Up)));
+        assert!(sim.apply(&turn(Direction::Left)));
+        assert!(sim.apply(&turn(Direction::Up)));
+        assert!(!sim.apply(&turn(Direction::Right)));
+        sim.step(&[]);
+        assert_eq!(sim.snakes[&id(1)].dir, Direction::Left);
+        sim.step(&[]);
+        assert_eq!(sim.snakes[&id(1)].dir, Direction::Up);
+    }
+
+    #[test]
+    fn eating_grows_the_snake_and_respawns_the_food() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        let food = sim.food;
+        let body: Vec<Pos> = (1..6).map(|dx| pos(food.x + dx, food.y)).collect();
+        place(&mut sim, id(1), &body, Direction::Left);
+        let events = sim.step(&[]);
+        assert!(events.contains(&Event::Ate { id: id(1) }));
+        assert!(events.contains(&Event::FoodSpawned { food: sim.food }));
+        let snake = &sim.snakes[&id(1)];
+        assert_eq!(snake.body.len(), 6);
+        assert_eq!(snake.score, 1);
+        assert_eq!(snake.body[0], food);
+    }
+
+    #[test]
+    fn respawn_food_follows_the_seed() {
+        let mut first = Simulation::new(TICK_MS, 3, 9);
+        let mut second = Simulation::new(TICK_MS, 3, 9);
+        first.step(&[Input::RespawnFood]);
+        second.step(&[Input::RespawnFood]);
+        assert_eq!(first.food, second.food);
+    }
+
+    #[test]
+    fn paused_snakes_stay_in_place() {
+        let mut sim = Simulation::new(TICK_MS, 3, 1);
+        place(&mut sim, id(1), &[pos(5, 5), pos(5, 6)], Direction::Up);
+        let events = sim.step(&[Input::Pause { paused: true }]);
+        assert!(events.is_empty());
+        assert_eq!(sim.snakes[&id(1)].body[0], pos(5, 5));
+        let events = sim.step(&[Input::Pause { paused: false }]);
+        assert_eq!(
+            events,
+            [Event::Moved {
+                id: id(1),
+                dir: Direction::Up
+            }]
+        );
+    }
+}